@@ -0,0 +1,56 @@
+// Times recursive calls with the naive fibonacci function.
+// Run with `cargo bench`: the time per call should stay about the same
+// as the number of calls grows, because calls share one stack and
+// scopes are compared by identity rather than by their variables.
+
+use std::time::Instant;
+
+use hlvm_runtime::stack::*;
+use hlvm_runtime::parser::parse;
+
+const SIZES: &[usize] = &[15, 20, 25];
+
+// fib(n) is n for n < 2, and fib(n - 1) + fib(n - 2) otherwise
+const FIB: &str = "
+{
+    \"n\" store
+    { 1 \"n\" load sub \"fib\" load call 2 \"n\" load sub \"fib\" load call add }
+    { \"n\" load }
+    2 \"n\" load less if call
+} \"fib\" store
+";
+
+
+// the number of calls fib(n) makes, counting its own
+fn calls(n: usize) -> usize {
+    let (mut a, mut b) = (1, 1);
+    for _ in 0..n {
+        let next = a + b + 1;
+        a = b;
+        b = next;
+    }
+    a
+}
+
+fn fib(n: usize) {
+    let program = parse(&format!("{} {} \"fib\" load call", FIB, n)).unwrap();
+    let mut frame = StackFrame::from_instructions(program);
+
+    let start = Instant::now();
+    frame.run().unwrap();
+    let elapsed = start.elapsed();
+
+    let operations = calls(n);
+    println!(
+        "{:<28} {:>8} calls {:>10.1} ns/call",
+        format!("fib({})", n),
+        operations,
+        elapsed.as_nanos() as f64 / operations as f64
+    );
+}
+
+fn main() {
+    for n in SIZES {
+        fib(*n);
+    }
+}
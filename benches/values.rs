@@ -0,0 +1,99 @@
+// Times list appends and attribute access on values of growing size.
+// Run with `cargo bench`: the time per operation should stay about
+// the same as the sizes grow, because values share their storage and
+// only copy it when a shared value is changed.
+
+use std::time::Instant;
+
+use hlvm_runtime::value::*;
+use hlvm_runtime::object::*;
+use hlvm_runtime::literals::*;
+
+const SIZES: &[usize] = &[1_000, 10_000, 100_000];
+
+
+// run `f` once and print the average time of its `operations`
+fn bench<F: FnOnce()>(name: &str, operations: usize, f: F) {
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+    println!(
+        "{:<28} {:>8} ops {:>10.1} ns/op",
+        name,
+        operations,
+        elapsed.as_nanos() as f64 / operations as f64
+    );
+}
+
+fn list_append(size: usize) {
+    bench("list append", size, || {
+        let mut l = list(&[]);
+        for i in 0..size {
+            l.list_push(Value::from_number((i as i32).into()));
+        }
+        assert_eq!(l.get_list().len(), size);
+    });
+}
+
+fn list_index(size: usize) {
+    let mut l = list(&[]);
+    for i in 0..size {
+        l.list_push(Value::from_number((i as i32).into()));
+    }
+
+    bench("list index", size, || {
+        for i in 0..size {
+            l.index(Value::from_number((i as i32).into()));
+        }
+    });
+}
+
+fn attribute_set(size: usize) {
+    bench("attribute set", size, || {
+        let mut instance = empty_obj();
+        for i in 0..size {
+            instance.set_attr(i.to_string(), num("1"));
+        }
+        assert_eq!(instance.get_attributes().len(), size);
+    });
+}
+
+fn attribute_get(size: usize) {
+    let mut instance = empty_obj();
+    let names: Vec<String> = (0..size).map(|i| i.to_string()).collect();
+    for name in &names {
+        instance.set_attr(name.clone(), num("1"));
+    }
+
+    bench("attribute get", size, || {
+        for name in &names {
+            instance.get_attr(name.clone());
+        }
+    });
+}
+
+fn nested_attribute_set(size: usize) {
+    let mut instance = empty_obj();
+    for i in 0..size {
+        instance.set_attr(i.to_string(), num("1"));
+    }
+    let mut outer = empty_obj();
+    outer.set_attr("inner".to_string(), instance);
+
+    bench("nested attribute set", size, || {
+        for i in 0..size {
+            outer.set_attr_recursive(vec!["inner".to_string(), i.to_string()], num("2")).ok();
+        }
+    });
+}
+
+fn main() {
+    for size in SIZES {
+        list_append(*size);
+        list_index(*size);
+        attribute_set(*size);
+        attribute_get(*size);
+        nested_attribute_set(*size);
+        println!();
+    }
+}
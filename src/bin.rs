@@ -1,43 +1,319 @@
-pub mod number;
-pub mod stack;
-pub mod table;
-pub mod object;
-pub mod value;
-pub mod error;
-pub mod literals;
-
-use stack::*;
-use object::Instruction::*;
-use literals::*;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use hlvm_runtime::stack::*;
+use hlvm_runtime::value::*;
+use hlvm_runtime::parser::parse;
+use hlvm_runtime::disassembler::{disassemble, render};
+use hlvm_runtime::bytecode::MAGIC;
+use hlvm_runtime::literals::list;
+use hlvm_runtime::error::{ErrorKind, RuntimeError};
+
+const USAGE: &str = "usage: hlvm_runtime <command> [options] [file]
+
+commands:
+    run <file>            run a program
+    dump <file>           print the parsed instruction tree of a program
+    check <file>          parse a program without running it
+    repl                  read and run one line of assembly at a time
+
+options:
+    --trace               print each instruction to stderr before it runs
+    --max-steps <n>       stop with an error after running n instructions
+    --timeout <ms>        stop with an error after running for ms milliseconds
+    --max-call-depth <n>  fail when calls are nested more than n deep (default 1000)
+    --max-stack <n>       fail when the stack holds more than n values
+    --max-value-size <n>  fail when a list, string or map grows past n cells
+
+Programs are read as bytecode if they start with the bytecode
+header, and as assembly text otherwise.";
+
+const REPL_HELP: &str = "Each line is run as assembly on the same stack, which is
+printed after every line along with the variables in scope.
+
+    :stack    print the stack
+    :scope    print the variables in scope
+    :reset    start over with an empty stack and scope
+    :help     print this message
+    :quit     leave the repl";
+
+// exit codes for failures that happen before a program runs
+const USAGE_ERROR: i32 = 2;
+const READ_ERROR: i32 = 3;
+const LOAD_ERROR: i32 = 4;
+
+// exit codes for runtime errors
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::StackUnderflow => 10,
+        ErrorKind::MissingInstance => 11,
+        ErrorKind::MissingAttribute => 12,
+        ErrorKind::InvalidNumber => 13,
+        ErrorKind::OutOfFuel => 14,
+        ErrorKind::Aborted => 15,
+        ErrorKind::CallDepthExceeded => 16,
+        ErrorKind::StackOverflow => 17,
+        ErrorKind::ValueTooLarge => 18,
+        ErrorKind::ForeignFunction => 19,
+        ErrorKind::TypeMismatch => 20,
+        ErrorKind::Raised => 21,
+    }
+}
+
+
+struct Options {
+    command: String,
+    file: Option<String>,
+    trace: bool,
+    max_steps: Option<usize>,
+    timeout: Option<u64>,
+    limits: Limits,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    exit(USAGE_ERROR);
+}
+
+fn parse_args(args: Vec<String>) -> Options {
+    let mut positional = vec![];
+    let mut trace = false;
+    let mut max_steps = None;
+    let mut timeout = None;
+    let mut limits = Limits::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--max-steps" => max_steps = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => Some(n),
+                _ => usage_error("--max-steps expects a number of steps")
+            },
+            "--timeout" => timeout = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => Some(n),
+                _ => usage_error("--timeout expects a number of milliseconds")
+            },
+            "--max-call-depth" => limits.max_call_depth = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => Some(n),
+                _ => usage_error("--max-call-depth expects a number of calls")
+            },
+            "--max-stack" => limits.max_stack_length = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => Some(n),
+                _ => usage_error("--max-stack expects a number of values")
+            },
+            "--max-value-size" => limits.max_value_size = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => Some(n),
+                _ => usage_error("--max-value-size expects a number of cells")
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            },
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option `{}`", arg)),
+            _ => positional.push(arg)
+        }
+    }
+
+    if positional.is_empty() {
+        usage_error("expected a command");
+    }
+
+    let command = positional.remove(0);
+    let file = match (command.as_str(), positional.len()) {
+        ("repl", 0) => None,
+        ("repl", _) => usage_error("the repl does not take a file"),
+        (_, 1) => positional.pop(),
+        _ => usage_error("expected a single file")
+    };
+    Options {command, file, trace, max_steps, timeout, limits}
+}
+
+// read a program from a file of either bytecode or assembly text
+fn load(file: &str) -> Value {
+    let bytes = match fs::read(file) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("==[ ERROR ]========> could not read {}: {}", file, e);
+            exit(READ_ERROR);
+        }
+    };
+
+    let program = if bytes.starts_with(MAGIC) {
+        Value::from_bytes(&bytes).map_err(|e| e.to_string())
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(source) => parse(&source).map_err(|e| e.to_string()),
+            Err(_) => Err("file is not valid utf-8".to_string())
+        }
+    };
+
+    match program {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("==[ ERROR ]========> {}: {}", file, e);
+            exit(LOAD_ERROR);
+        }
+    }
+}
+
+fn report(e: &RuntimeError) {
+    eprintln!("==[ ERROR ]========> {}", e);
+    eprintln!("==[ STACK TRACE ]==> {}", render_values(e.stack()));
+}
+
+fn render_values(values: &[Value]) -> String {
+    let rendered: Vec<String> = values.iter().map(render).collect();
+    format!("[{}]", rendered.join(", "))
+}
+
+fn new_frame(options: &Options, instructions: Value) -> StackFrame {
+    let mut frame = StackFrame::from_instructions(instructions);
+    frame.set_trace(options.trace);
+    frame.set_limits(options.limits);
+    frame.set_fuel(options.max_steps);
+    frame.set_deadline(options.timeout.map(|ms| Instant::now() + Duration::from_millis(ms)));
+    frame
+}
+
+fn print_stack(frame: &StackFrame) {
+    println!("stack: {}", render_values(&frame.contents()));
+}
+
+fn print_scope(frame: &StackFrame) {
+    for (name, value) in frame.scope().variables() {
+        println!("{} = {}", name, render(&value));
+    }
+}
+
+fn repl(options: &Options) {
+    let mut frame = new_frame(options, list(&[]));
+    let stdin = io::stdin();
+
+    loop {
+        print!("hlvm> ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                println!();
+                return;
+            },
+            Ok(_) => {}
+        }
+
+        match line.trim() {
+            "" => continue,
+            ":stack" => print_stack(&frame),
+            ":scope" => print_scope(&frame),
+            ":reset" => frame = new_frame(options, list(&[])),
+            ":help" => println!("{}", REPL_HELP),
+            ":quit" => return,
+            command if command.starts_with(':') => {
+                eprintln!("unknown command `{}`, try :help", command);
+            },
+            source => {
+                match parse(source) {
+                    Ok(instructions) => {
+                        if let Err(e) = frame.execute(instructions) {
+                            report(&e);
+                        }
+                    },
+                    Err(e) => eprintln!("==[ ERROR ]========> {}", e)
+                }
+                print_stack(&frame);
+                print_scope(&frame);
+            }
+        }
+    }
+}
 
 fn main() {
-    StackFrame::from_instructions(
-        fun(&[
-            string("hey jude"),
-            empty_obj(),
-            string("a"),
-            string("b"),
-            string("c"),
-            string("d"),
-            ins(SetAttr),
-
-            string("self"),
-            ins(Store),
-            
-            string("self"),
-            ins(Load),
-            ins(Println),
-
-
-            string("self"),
-            ins(Load),
-
-            string("a"),
-            string("b"),
-            string("c"),
-            string("d"),
-            ins(GetAttr),
-            ins(Println),
-        ])
-    ).run();
-}
\ No newline at end of file
+    let options = parse_args(env::args().skip(1).collect());
+    let file = options.file.clone().unwrap_or_default();
+
+    match options.command.as_str() {
+        "run" => {
+            let mut frame = new_frame(&options, load(&file));
+            if let Err(e) = frame.run() {
+                report(&e);
+                exit(exit_code(e.kind()));
+            }
+        },
+        "dump" => print!("{}", disassemble(&load(&file))),
+        "check" => {
+            load(&file);
+            println!("{}: ok", file);
+        },
+        "repl" => repl(&options),
+        _ => usage_error(&format!("unknown command `{}`", options.command))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_are_read_around_the_file() {
+        let options = parse_args(args("run --trace program.hlvm --max-steps 100 --timeout 50"));
+        assert_eq!(options.command, "run");
+        assert_eq!(options.file.as_deref(), Some("program.hlvm"));
+        assert!(options.trace);
+        assert_eq!(options.max_steps, Some(100));
+        assert_eq!(options.timeout, Some(50));
+    }
+
+    #[test]
+    fn limits_default_to_the_library_defaults() {
+        let options = parse_args(args("run program.hlvm"));
+        assert_eq!(options.limits, Limits::default());
+
+        let options = parse_args(args("run --max-call-depth 50 --max-stack 1000 --max-value-size 4096 program.hlvm"));
+        assert_eq!(options.limits, Limits {
+            max_call_depth: Some(50),
+            max_stack_length: Some(1000),
+            max_value_size: Some(4096)
+        });
+    }
+
+    #[test]
+    fn the_repl_takes_no_file() {
+        let options = parse_args(args("repl"));
+        assert_eq!(options.command, "repl");
+        assert_eq!(options.file, None);
+        assert!(!options.trace);
+    }
+
+    #[test]
+    fn runtime_errors_have_their_own_exit_codes() {
+        let kinds = [
+            ErrorKind::StackUnderflow,
+            ErrorKind::MissingInstance,
+            ErrorKind::MissingAttribute,
+            ErrorKind::InvalidNumber,
+            ErrorKind::OutOfFuel,
+            ErrorKind::Aborted,
+            ErrorKind::CallDepthExceeded,
+            ErrorKind::StackOverflow,
+            ErrorKind::ValueTooLarge,
+            ErrorKind::ForeignFunction,
+            ErrorKind::TypeMismatch,
+            ErrorKind::Raised,
+        ];
+        let mut codes: Vec<i32> = kinds.iter().map(|kind| exit_code(*kind)).collect();
+        assert!(codes.iter().all(|code| ![0, 1, USAGE_ERROR, READ_ERROR, LOAD_ERROR].contains(code)));
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), kinds.len());
+    }
+}
@@ -0,0 +1,538 @@
+// The binary bytecode format for HLVM values.
+//
+// All integers are little endian.
+//
+//   header     the magic bytes `HLVM` followed by the version as a u16
+//   constants  a u32 count, then for each constant a u8 kind
+//              (0 for strings, 1 for numbers), a u32 byte length
+//              and the utf-8 text of the string or number
+//   value      a u8 type tag followed by its payload:
+//
+//     0  None      nothing
+//     1  Str       u32 index of a string constant
+//     2  Num       u32 index of a number constant
+//     3  List      u32 length followed by that many values
+//     4  Function  u32 length followed by that many values
+//     5  Instance  u32 length followed by that many pairs of
+//                  a u32 string constant index and a value
+//     6  Problem   u8 index into `PROBLEMS`, u32 index of the string
+//                  constant of its message, then a u32 length of 0 or 1
+//                  followed by its payload if it has one
+//     7  Command   u8 index into `INSTRUCTIONS`
+//     8  Map       u32 length followed by that many pairs
+//                  of a key and a value, in key order
+//     9  Bool      u8 1 for true or 0 for false
+//
+// New instructions and problems must only ever be appended to
+// their tables, so that old bytecode keeps decoding the same way.
+// Version 1 bytecode is still decoded: its problems are just the u8
+// index, and are given the default message of their kind. Values may be
+// nested at most `MAX_NESTING` deep, counting the outermost one.
+
+use std::collections::HashMap;
+
+use crate::value::*;
+use crate::object::*;
+use crate::number::Number;
+use crate::table::Table;
+use crate::map::*;
+use crate::error::BytecodeError;
+
+pub const MAGIC: &[u8; 4] = b"HLVM";
+pub const VERSION: u16 = 2;
+pub const MAX_NESTING: usize = 256;
+
+const STRING_CONSTANT: u8 = 0;
+const NUMBER_CONSTANT: u8 = 1;
+
+const NOTHING_TAG: u8 = 0;
+const STR_TAG: u8 = 1;
+const NUM_TAG: u8 = 2;
+const LIST_TAG: u8 = 3;
+const FUNCTION_TAG: u8 = 4;
+const INSTANCE_TAG: u8 = 5;
+const PROBLEM_TAG: u8 = 6;
+const COMMAND_TAG: u8 = 7;
+const MAP_TAG: u8 = 8;
+const BOOL_TAG: u8 = 9;
+
+pub const INSTRUCTIONS: &[Instruction] = &[
+    Instruction::Print,
+    Instruction::Println,
+    Instruction::While,
+    Instruction::If,
+    Instruction::ListFromStack,
+    Instruction::Append,
+    Instruction::Pop,
+    Instruction::Index,
+    Instruction::Equal,
+    Instruction::Greater,
+    Instruction::Less,
+    Instruction::Not,
+    Instruction::Add,
+    Instruction::Mul,
+    Instruction::Sub,
+    Instruction::Div,
+    Instruction::Mod,
+    Instruction::Call,
+    Instruction::Load,
+    Instruction::Store,
+    Instruction::GetAttr,
+    Instruction::SetAttr,
+    Instruction::Execute,
+    Instruction::Pass,
+    Instruction::IsProblem,
+    Instruction::ProblemKind,
+    Instruction::ProblemMessage,
+    Instruction::ProblemPayload,
+    Instruction::Raise,
+    Instruction::Try,
+    Instruction::Slice,
+    Instruction::NewMap,
+    Instruction::MapInsert,
+    Instruction::MapGet,
+    Instruction::MapRemove,
+    Instruction::MapContains,
+    Instruction::MapKeys,
+    Instruction::MapValues,
+    Instruction::MapLength,
+    Instruction::And,
+    Instruction::Or,
+    Instruction::Xor,
+    Instruction::AndThen,
+    Instruction::OrElse,
+    Instruction::Pow,
+    Instruction::IntDiv,
+    Instruction::Abs,
+    Instruction::Neg,
+    Instruction::Floor,
+    Instruction::Ceil,
+    Instruction::Round,
+    Instruction::Trunc,
+    Instruction::Min,
+    Instruction::Max,
+    Instruction::Sqrt,
+    Instruction::Exp,
+    Instruction::Ln,
+    Instruction::Log10,
+    Instruction::Sin,
+    Instruction::Cos,
+    Instruction::Tan,
+    Instruction::Asin,
+    Instruction::Acos,
+    Instruction::Atan,
+    Instruction::BitAnd,
+    Instruction::BitOr,
+    Instruction::BitXor,
+    Instruction::Shl,
+    Instruction::Shr,
+    Instruction::IsNan,
+    Instruction::IsInfinite,
+    Instruction::FormatNum,
+    Instruction::ToRadix,
+    Instruction::ParseNum,
+];
+
+pub const PROBLEMS: &[Problem] = &[
+    Problem::IncompatibleTypes,
+    Problem::ValueError,
+    Problem::OutOfRange,
+    Problem::DivisionByZero,
+    Problem::KeyError,
+    Problem::TypeError,
+    Problem::ArityError,
+    Problem::Overflow,
+    Problem::RuntimeError,
+];
+
+
+// encode a value, with its header and constant pool
+pub fn encode(value: &Value) -> Result<Vec<u8>, BytecodeError> {
+    let mut encoder = Encoder {
+        constants: vec![],
+        indices: HashMap::new(),
+        body: vec![]
+    };
+    encoder.value(value)?;
+
+    let mut result = MAGIC.to_vec();
+    result.extend_from_slice(&VERSION.to_le_bytes());
+    result.extend_from_slice(&(encoder.constants.len() as u32).to_le_bytes());
+    for (kind, text) in &encoder.constants {
+        result.push(*kind);
+        result.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        result.extend_from_slice(text.as_bytes());
+    }
+    result.extend(encoder.body);
+    Ok(result)
+}
+
+// decode a value produced by `encode`
+pub fn decode(bytes: &[u8]) -> Result<Value, BytecodeError> {
+    let mut decoder = Decoder {
+        bytes,
+        position: 0,
+        version: VERSION,
+        constants: vec![],
+        depth: 0
+    };
+
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(BytecodeError::InvalidHeader);
+    }
+    decoder.position = MAGIC.len();
+
+    let version = u16::from_le_bytes([decoder.byte()?, decoder.byte()?]);
+    if version == 0 || version > VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    decoder.version = version;
+
+    decoder.constants()?;
+    let value = decoder.value()?;
+    if decoder.position != bytes.len() {
+        return Err(BytecodeError::TrailingBytes(decoder.position));
+    }
+    Ok(value)
+}
+
+
+struct Encoder {
+    constants: Vec<(u8, String)>,          // the constant pool in order
+    indices: HashMap<(u8, String), u32>,   // the index of each constant in the pool
+    body: Vec<u8>,                         // the encoded value
+}
+
+impl Encoder {
+    // add a constant to the pool if it isn't there already
+    fn constant(&mut self, kind: u8, text: String) {
+        let index = match self.indices.get(&(kind, text.clone())) {
+            Some(i) => *i,
+            None => {
+                let i = self.constants.len() as u32;
+                self.indices.insert((kind, text.clone()), i);
+                self.constants.push((kind, text));
+                i
+            }
+        };
+        self.u32(index);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.body.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn items(&mut self, items: &[Value]) -> Result<(), BytecodeError> {
+        self.u32(items.len() as u32);
+        for item in items {
+            self.value(item)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(), BytecodeError> {
+        match value.get_type() {
+            Type::Nothing => self.body.push(NOTHING_TAG),
+            Type::Str => {
+                self.body.push(STR_TAG);
+                self.constant(STRING_CONSTANT, value.get_string().to_string());
+            },
+            Type::Num => {
+                self.body.push(NUM_TAG);
+                self.constant(NUMBER_CONSTANT, value.as_number().to_string());
+            },
+            Type::Bool => {
+                self.body.push(BOOL_TAG);
+                self.body.push(value.as_bool() as u8);
+            },
+            Type::List => {
+                self.body.push(LIST_TAG);
+                self.items(value.get_list())?;
+            },
+            Type::Function => {
+                if value.is_foreign_function() {
+                    return Err(BytecodeError::ForeignFunction);
+                }
+                self.body.push(FUNCTION_TAG);
+                self.items(value.get_list())?;
+            },
+            Type::Instance => {
+                self.body.push(INSTANCE_TAG);
+                let table = value.get_attributes();
+                let mut keys = table.keys();
+                keys.sort();
+
+                self.u32(keys.len() as u32);
+                for key in keys {
+                    let attr = value.get_attr(key.clone());
+                    self.constant(STRING_CONSTANT, key);
+                    self.value(&attr)?;
+                }
+            },
+            Type::Map => {
+                self.body.push(MAP_TAG);
+                let map = value.get_map();
+                self.u32(map.len() as u32);
+                for (key, item) in map.iter() {
+                    self.value(&key.to_object())?;
+                    self.value(item)?;
+                }
+            },
+            Type::Problem(p) => {
+                self.body.push(PROBLEM_TAG);
+                self.body.push(position(PROBLEMS, p));
+                self.constant(STRING_CONSTANT, value.get_string().to_string());
+                self.items(value.get_list())?;
+            },
+            Type::Command(i) => {
+                self.body.push(COMMAND_TAG);
+                self.body.push(position(INSTRUCTIONS, i));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the index of an item in one of the tag tables
+fn position<T: PartialEq>(table: &[T], item: T) -> u8 {
+    table.iter()
+        .position(|t| *t == item)
+        .expect("every instruction and problem has a bytecode tag") as u8
+}
+
+
+enum Constant {
+    Str(String),
+    Num(Number),
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],           // the bytecode being decoded
+    position: usize,           // the offset of the next byte to read
+    version: u16,              // the version the bytes were encoded by
+    constants: Vec<Constant>,  // the decoded constant pool
+    depth: usize,              // how many values the next one is nested in
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], BytecodeError> {
+        if self.bytes.len() - self.position < length {
+            return Err(BytecodeError::UnexpectedEnd(self.bytes.len()));
+        }
+        let slice = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn constants(&mut self) -> Result<(), BytecodeError> {
+        let count = self.u32()?;
+        for _ in 0..count {
+            let start = self.position;
+            let kind = self.byte()?;
+            let length = self.u32()? as usize;
+            let text = match std::str::from_utf8(self.take(length)?) {
+                Ok(t) => t.to_string(),
+                Err(_) => return Err(BytecodeError::InvalidString(start))
+            };
+
+            self.constants.push(match kind {
+                STRING_CONSTANT => Constant::Str(text),
+                NUMBER_CONSTANT => match text.parse() {
+                    Ok(n) => Constant::Num(n),
+                    Err(_) => return Err(BytecodeError::InvalidNumber(start))
+                },
+                _ => return Err(BytecodeError::InvalidTag(start, kind))
+            });
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let start = self.position;
+        let index = self.u32()?;
+        match self.constants.get(index as usize) {
+            Some(Constant::Str(s)) => Ok(s.clone()),
+            _ => Err(BytecodeError::InvalidConstant(start, index))
+        }
+    }
+
+    fn number(&mut self) -> Result<Number, BytecodeError> {
+        let start = self.position;
+        let index = self.u32()?;
+        match self.constants.get(index as usize) {
+            Some(Constant::Num(n)) => Ok(n.clone()),
+            _ => Err(BytecodeError::InvalidConstant(start, index))
+        }
+    }
+
+    fn items(&mut self) -> Result<Vec<Value>, BytecodeError> {
+        let length = self.u32()?;
+        let mut items = vec![];
+        for _ in 0..length {
+            items.push(self.value()?);
+        }
+        Ok(items)
+    }
+
+    // a value, refusing to recurse further than `MAX_NESTING`
+    // so that hostile bytes can't overflow the native stack
+    fn value(&mut self) -> Result<Value, BytecodeError> {
+        if self.depth == MAX_NESTING {
+            return Err(BytecodeError::TooDeep(self.position));
+        }
+        self.depth += 1;
+        let value = self.tagged();
+        self.depth -= 1;
+        value
+    }
+
+    fn tagged(&mut self) -> Result<Value, BytecodeError> {
+        let start = self.position;
+        let tag = self.byte()?;
+        Ok(match tag {
+            NOTHING_TAG => Value::from_nothing(),
+            STR_TAG => Value::from_string(self.string()?),
+            NUM_TAG => Value::from_number(self.number()?),
+            LIST_TAG => Value::from_vector(self.items()?),
+            FUNCTION_TAG => Value::from_function(self.items()?),
+            INSTANCE_TAG => {
+                let length = self.u32()?;
+                let mut table = Table::new();
+                for _ in 0..length {
+                    let key = self.string()?;
+                    table.set(key, self.value()?);
+                }
+                let mut instance = Value::empty_instance();
+                instance.set_attributes(table);
+                instance
+            },
+            BOOL_TAG => match self.byte()? {
+                0 => Value::from_bool(false),
+                1 => Value::from_bool(true),
+                b => return Err(BytecodeError::InvalidTag(start + 1, b))
+            },
+            MAP_TAG => {
+                let length = self.u32()?;
+                let mut map = Map::new();
+                for _ in 0..length {
+                    let position = self.position;
+                    let key = match Key::from_object(&self.value()?) {
+                        Some(k) => k,
+                        None => return Err(BytecodeError::InvalidKey(position))
+                    };
+                    map.insert(key, self.value()?);
+                }
+                Value::from_map(map)
+            },
+            PROBLEM_TAG => {
+                let index = self.byte()?;
+                let problem = match PROBLEMS.get(index as usize) {
+                    Some(p) => *p,
+                    None => return Err(BytecodeError::InvalidTag(start + 1, index))
+                };
+                if self.version < 2 {
+                    Value::from_problem(problem)
+                } else {
+                    let message = self.string()?;
+                    let payload = self.items()?.into_iter().next();
+                    Value::from_problem_with(problem, &message, payload)
+                }
+            },
+            COMMAND_TAG => {
+                let index = self.byte()?;
+                match INSTRUCTIONS.get(index as usize) {
+                    Some(i) => Value::from_instruction(*i),
+                    None => return Err(BytecodeError::InvalidTag(start + 1, index))
+                }
+            },
+            _ => return Err(BytecodeError::InvalidTag(start, tag))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::literals::*;
+
+    fn round_trip(value: &Value) -> Value {
+        decode(&encode(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        let program = fun(&[
+            string("hi"), num("1.5"), num("-12345678901234567890123"), none(), boolean(true), empty_obj(),
+            list(&[num("1"), string("a")]),
+            fun(&[num("1"), num("2"), ins(Instruction::Add)]),
+            ins(Instruction::Call)
+        ]);
+        assert_eq!(round_trip(&program), program);
+    }
+
+    #[test]
+    fn runtime_values_round_trip() {
+        let mut instance = empty_obj();
+        instance.set_attr("name".to_string(), string("value"));
+
+        let mut map = Map::new();
+        map.insert(Key::Str("a".to_string()), list(&[boolean(false)]));
+        map.insert(Key::Num(2.into()), none());
+
+        let problem = Value::from_problem_with(Problem::KeyError, "missing", Some(string("a")));
+        let value = list(&[instance, Value::from_map(map), problem, Value::from_problem(Problem::Overflow)]);
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn foreign_functions_cannot_be_encoded() {
+        let function = Value::from_foreign_function(|x| x);
+        assert_eq!(encode(&function), Err(BytecodeError::ForeignFunction));
+    }
+
+    #[test]
+    fn bad_bytes_are_rejected() {
+        let bytes = encode(&string("hi")).unwrap();
+        assert_eq!(decode(b"HLV"), Err(BytecodeError::InvalidHeader));
+        assert_eq!(decode(b"NOPE\x02\x00"), Err(BytecodeError::InvalidHeader));
+        assert_eq!(decode(b"HLVM\x63\x00"), Err(BytecodeError::UnsupportedVersion(99)));
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), Err(BytecodeError::UnexpectedEnd(_))));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(BytecodeError::TrailingBytes(bytes.len())));
+
+        let mut tagged = bytes;
+        let tag = tagged.len() - 5;
+        tagged[tag] = 200;
+        assert_eq!(decode(&tagged), Err(BytecodeError::InvalidTag(tag, 200)));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        // `lists` lists inside one another, around nothing
+        let nested = |lists: usize| {
+            let mut bytes = b"HLVM\x02\x00\x00\x00\x00\x00".to_vec();
+            for _ in 0..lists {
+                bytes.extend_from_slice(&[LIST_TAG, 1, 0, 0, 0]);
+            }
+            bytes.push(NOTHING_TAG);
+            bytes
+        };
+        assert!(decode(&nested(MAX_NESTING - 1)).is_ok());
+
+        let bytes = nested(100_000);
+        let innermost = 10 + 5 * MAX_NESTING;
+        assert_eq!(decode(&bytes), Err(BytecodeError::TooDeep(innermost)));
+    }
+}
@@ -0,0 +1,418 @@
+// Conversions between HLVM values and rust types, so that hosts can
+// register ordinary rust functions as native functions:
+//
+//   registry.register_fn("text.repeat", |s: &str, n: i64| s.repeat(n as usize));
+//
+// The arity of the native function is the number of parameters, and
+// an argument of the wrong type fails with a TypeMismatch error naming
+// the argument, instead of being read as zero. String parameters may be
+// `&str`, borrowing the argument for the length of the call, or `String`.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::value::*;
+use crate::object::*;
+use crate::literals::*;
+use crate::number::Number;
+use crate::table::Table;
+use crate::error::*;
+
+
+// a rust type that can be read from a value
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+// a rust type that can be turned into a value
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+// what a native function can return: a value, or a value or an error
+pub trait IntoNativeResult {
+    fn into_native_result(self) -> Result<Value, RuntimeError>;
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+    fn into_native_result(self) -> Result<Value, RuntimeError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> IntoNativeResult for Result<T, RuntimeError> {
+    fn into_native_result(self) -> Result<Value, RuntimeError> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+
+fn mismatch(expected: &str, value: &Value) -> RuntimeError {
+    let found = match value.get_type() {
+        Type::Num => value.as_number().to_string(),
+        t => format!("{:?}", t)
+    };
+    RuntimeError::new(
+        ErrorKind::TypeMismatch,
+        &format!("expected {}, found {}", expected, found)
+    )
+}
+
+fn expect_type(value: &Value, value_type: Type, expected: &str) -> Result<(), RuntimeError> {
+    if value.get_type() != value_type {
+        return Err(mismatch(expected, value));
+    }
+    Ok(())
+}
+
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Str, "a string")?;
+        Ok(value.get_string().to_string())
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::from_string(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        string(self)
+    }
+}
+
+impl FromValue for Number {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Num, "a number")?;
+        Ok(value.as_number())
+    }
+}
+
+impl IntoValue for Number {
+    fn into_value(self) -> Value {
+        Value::from_number(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Num, "an integer")?;
+        match value.as_number().to_i64() {
+            Some(n) => Ok(n),
+            None => Err(mismatch("an integer", value))
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::from_number(Number::from(self))
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Num, "a non-negative integer")?;
+        match value.as_number().to_u64() {
+            Some(n) => Ok(n),
+            None => Err(mismatch("a non-negative integer", value))
+        }
+    }
+}
+
+impl IntoValue for u64 {
+    fn into_value(self) -> Value {
+        Value::from_number(Number::from(self))
+    }
+}
+
+impl FromValue for u128 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Num, "a non-negative integer")?;
+        match value.as_number().to_u128() {
+            Some(n) => Ok(n),
+            None => Err(mismatch("a non-negative integer", value))
+        }
+    }
+}
+
+impl IntoValue for u128 {
+    fn into_value(self) -> Value {
+        Value::from_number(Number::from(self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Num, "a number")?;
+        Ok(value.as_number().to_f64())
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::from_f64(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Bool, "a boolean")?;
+        Ok(value.as_bool())
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        boolean(self)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        none()
+    }
+}
+
+// None is the None value, and anything else is read as the inner type
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        if value.get_type() == Type::Nothing {
+            return Ok(None);
+        }
+        T::from_value(value).map(Some)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => none()
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::List, "a list")?;
+        value.get_list().iter().map(T::from_value).collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::from_vector(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+// maps are instances, with an attribute for each key
+impl<T: FromValue, S: BuildHasher + Default> FromValue for HashMap<String, T, S> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        expect_type(value, Type::Instance, "an instance")?;
+        value.get_attributes().iter()
+            .map(|(k, v)| Ok((k.clone(), T::from_value(v)?)))
+            .collect()
+    }
+}
+
+impl<T: IntoValue, S: BuildHasher> IntoValue for HashMap<String, T, S> {
+    fn into_value(self) -> Value {
+        let mut table = Table::new();
+        for (k, v) in self {
+            table.set(k, v.into_value());
+        }
+        let mut instance = empty_obj();
+        instance.set_attributes(table);
+        instance
+    }
+}
+
+// tuples are lists with exactly one item per element
+macro_rules! tuple {
+    ($length:expr, $($name:ident $index:tt),+) => {
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+                expect_type(value, Type::List, &format!("a list of {} items", $length))?;
+                let items = value.get_list();
+                if items.len() != $length {
+                    return Err(RuntimeError::new(
+                        ErrorKind::TypeMismatch,
+                        &format!("expected a list of {} items, found {} items", $length, items.len())
+                    ));
+                }
+                Ok(($($name::from_value(&items[$index])?,)+))
+            }
+        }
+
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            fn into_value(self) -> Value {
+                list(&[$(self.$index.into_value()),+])
+            }
+        }
+    };
+}
+
+tuple!(1, A 0);
+tuple!(2, A 0, B 1);
+tuple!(3, A 0, B 1, C 2);
+tuple!(4, A 0, B 1, C 2, D 3);
+tuple!(5, A 0, B 1, C 2, D 3, E 4);
+tuple!(6, A 0, B 1, C 2, D 3, E 4, F 5);
+
+
+// a parameter of a native function, read from an argument that lives
+// for 'a. `FromValue` types are read as themselves, and a `&str` borrows
+// the argument's string, so it can't outlive the call
+pub trait Argument<'a> {
+    type Type;
+    fn read(value: &'a Value) -> Result<Self::Type, RuntimeError>;
+}
+
+impl<'a, T: FromValue> Argument<'a> for T {
+    type Type = T;
+
+    fn read(value: &'a Value) -> Result<T, RuntimeError> {
+        T::from_value(value)
+    }
+}
+
+impl<'a> Argument<'a> for &str {
+    type Type = &'a str;
+
+    fn read(value: &'a Value) -> Result<&'a str, RuntimeError> {
+        expect_type(value, Type::Str, "a string")?;
+        Ok(value.get_string())
+    }
+}
+
+
+// a rust function that can be registered as a native function,
+// implemented for functions of up to six `Argument` parameters
+pub trait NativeFunction<Parameters> {
+    fn arity(&self) -> usize;
+    fn call(&self, arguments: Vec<Value>) -> Result<Value, RuntimeError>;
+}
+
+// read an argument, saying which one it was if it has the wrong type
+fn argument<'a, T: Argument<'a>>(arguments: &'a [Value], index: usize) -> Result<T::Type, RuntimeError> {
+    T::read(&arguments[index]).map_err(|e| RuntimeError::new(
+        e.kind(),
+        &format!("argument {}: {}", index + 1, e.message())
+    ))
+}
+
+// the first `Fn` bound names the parameter types, and the second says
+// the function takes arguments borrowed for any lifetime, which is
+// what lets a `&str` parameter borrow from the arguments
+macro_rules! native_function {
+    ($arity:expr $(, $name:ident $index:tt)*) => {
+        impl<Function, Return, $($name),*> NativeFunction<($($name,)*)> for Function
+                where Function: Fn($($name),*) -> Return,
+                      Function: for<'a> Fn($(<$name as Argument<'a>>::Type),*) -> Return,
+                      Return: IntoNativeResult,
+                      $($name: for<'a> Argument<'a>),* {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+                if arguments.len() != $arity {
+                    return Ok(arity_problem($arity, arguments.len()));
+                }
+                (self)($(argument::<$name>(&arguments, $index)?),*).into_native_result()
+            }
+        }
+    };
+}
+
+native_function!(0);
+native_function!(1, A 0);
+native_function!(2, A 0, B 1);
+native_function!(3, A 0, B 1, C 2);
+native_function!(4, A 0, B 1, C 2, D 3);
+native_function!(5, A 0, B 1, C 2, D 3, E 4);
+native_function!(6, A 0, B 1, C 2, D 3, E 4, F 5);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from<T: FromValue>(value: Value) -> Result<T, RuntimeError> {
+        T::from_value(&value)
+    }
+
+    #[test]
+    fn values_round_trip_through_rust_types() {
+        assert_eq!(from::<i64>((-12i64).into_value()).unwrap(), -12);
+        assert_eq!(from::<u128>(u128::MAX.into_value()).unwrap(), u128::MAX);
+        assert_eq!(from::<String>("hi".into_value()).unwrap(), "hi");
+        assert!(from::<bool>(true.into_value()).unwrap());
+        assert_eq!(from::<Option<i64>>(none()).unwrap(), None);
+        assert_eq!(from::<Vec<i64>>(vec![1i64, 2].into_value()).unwrap(), [1, 2]);
+        assert_eq!(from::<(String, f64)>(("a", 1.5).into_value()).unwrap(), ("a".to_string(), 1.5));
+
+        let mut map = HashMap::new();
+        map.insert("x".to_string(), 1i64);
+        assert_eq!(from::<HashMap<String, i64>>(map.clone().into_value()).unwrap(), map);
+    }
+
+    #[test]
+    fn mismatched_values_are_errors() {
+        assert_eq!(from::<i64>(string("1")).unwrap_err().kind(), ErrorKind::TypeMismatch);
+        assert_eq!(from::<i64>(num("1.5")).unwrap_err().message(), "expected an integer, found 1.5");
+        assert_eq!(from::<u64>(num("-1")).unwrap_err().kind(), ErrorKind::TypeMismatch);
+        assert_eq!(from::<(i64, i64)>(vec![1i64].into_value()).unwrap_err().kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn native_functions_name_the_bad_argument() {
+        let repeat = |s: String, n: i64| s.repeat(n as usize);
+        assert_eq!(NativeFunction::arity(&repeat), 2);
+        assert_eq!(NativeFunction::call(&repeat, vec![string("ab"), num("2")]).unwrap(), string("abab"));
+
+        let error = NativeFunction::call(&repeat, vec![string("ab"), string("2")]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+        assert!(error.message().starts_with("argument 2: "));
+    }
+
+    #[test]
+    fn string_parameters_can_be_borrowed() {
+        fn join(a: &str, b: &str) -> String {
+            format!("{}{}", a, b)
+        }
+        assert_eq!(NativeFunction::call(&join, vec![string("a"), string("b")]).unwrap(), string("ab"));
+
+        let length = |s: &str| s.chars().count() as i64;
+        assert_eq!(NativeFunction::call(&length, vec![string("héllo")]).unwrap(), num("5"));
+
+        let error = NativeFunction::call(&length, vec![num("1")]).unwrap_err();
+        assert_eq!(error.message(), "argument 1: expected a string, found 1");
+    }
+
+    #[test]
+    fn typed_functions_check_their_argument_count() {
+        let add = |a: i64, b: i64| a + b;
+        let result = NativeFunction::call(&add, vec![1i64.into_value()]).unwrap();
+        assert_eq!(result.get_type(), Type::Problem(Problem::ArityError));
+        assert_eq!(result.problem_message(), Some("expected 2 arguments, found 1"));
+    }
+}
@@ -0,0 +1,245 @@
+// Hooks for watching and debugging a running program.
+//
+// A `Hook` given to `StackFrame::set_hook` is called before every
+// instruction the stackframe runs, including the instructions of called
+// functions and loop bodies, with a `Context` showing the instruction,
+// its offset and function, the call depth and the stack. Returning
+// `Control::Abort` stops the program with an Aborted error.
+//
+// `Debugger` is a hook that pauses at breakpoints and while stepping
+// into, over or out of calls, asking a callback what to do next.
+
+use crate::value::*;
+use crate::stack::*;
+
+
+// what a hook wants the stackframe to do after it returns
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Control {
+    Continue,   // run the instruction
+    Abort,      // stop the program with an Aborted error
+}
+
+// a hook is called before every instruction a stackframe runs,
+// including the instructions of called functions and while loops
+pub trait Hook {
+    fn before_instruction(&mut self, context: &Context) -> Control;
+}
+
+
+// a read-only view of the instruction about to run
+pub struct Context<'a> {
+    frame: &'a StackFrame,     // the stackframe running the instruction
+    function: &'a Value,       // the function (or list) the instruction is in
+    index: usize,              // the offset of the instruction in that function
+    instruction: &'a Value,    // the instruction itself
+}
+
+impl<'a> Context<'a> {
+    pub fn new(frame: &'a StackFrame, function: &'a Value, index: usize, instruction: &'a Value) -> Self {
+        Self {frame, function, index, instruction}
+    }
+
+    pub fn frame(&self) -> &StackFrame {
+        self.frame
+    }
+
+    // how many calls deep the instruction is, starting at 0
+    pub fn depth(&self) -> usize {
+        self.frame.depth()
+    }
+
+    pub fn function(&self) -> &Value {
+        self.function
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn instruction(&self) -> &Value {
+        self.instruction
+    }
+
+    // the values on the operand stack, from the bottom to the top
+    pub fn stack(&self) -> Vec<Value> {
+        self.frame.contents()
+    }
+
+    // every scope a variable could be loaded from, innermost first
+    pub fn scopes(&self) -> Vec<&Scope> {
+        self.frame.scopes()
+    }
+}
+
+
+// pause before the instruction at an offset in a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    function: Value,
+    offset: usize,
+}
+
+impl Breakpoint {
+    pub fn new(function: Value, offset: usize) -> Self {
+        Self {function, offset}
+    }
+
+    pub fn matches(&self, context: &Context) -> bool {
+        self.offset == context.index() && self.function == *context.function()
+    }
+}
+
+
+// what to do after the debugger pauses
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    Continue,   // run until the next breakpoint
+    StepInto,   // pause at the very next instruction
+    StepOver,   // pause at the next instruction that isn't in a deeper call
+    StepOut,    // pause at the next instruction after the current call returns
+    Abort,      // stop the program
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Continue,
+    StepInto,
+    StepOver(usize),   // the depth stepped over from
+    StepOut(usize),    // the depth stepped out of
+}
+
+// a hook that pauses at breakpoints and while stepping,
+// asking `on_pause` what to do each time it pauses
+pub struct Debugger<F: FnMut(&Context) -> Command> {
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    on_pause: F,
+}
+
+impl<F: FnMut(&Context) -> Command> Debugger<F> {
+    pub fn new(on_pause: F) -> Self {
+        Self {
+            breakpoints: vec![],
+            mode: Mode::Continue,
+            on_pause
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) {
+        self.breakpoints.retain(|b| b != breakpoint);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // pause at the first instruction instead of the first breakpoint
+    pub fn pause_at_start(&mut self) {
+        self.mode = Mode::StepInto;
+    }
+}
+
+impl<F: FnMut(&Context) -> Command> Hook for Debugger<F> {
+    fn before_instruction(&mut self, context: &Context) -> Control {
+        let depth = context.depth();
+        let stepped = match self.mode {
+            Mode::Continue => false,
+            Mode::StepInto => true,
+            Mode::StepOver(d) => depth <= d,
+            Mode::StepOut(d) => depth < d,
+        };
+
+        if !stepped && !self.breakpoints.iter().any(|b| b.matches(context)) {
+            return Control::Continue;
+        }
+
+        self.mode = match (self.on_pause)(context) {
+            Command::Continue => Mode::Continue,
+            Command::StepInto => Mode::StepInto,
+            Command::StepOver => Mode::StepOver(depth),
+            Command::StepOut => Mode::StepOut(depth),
+            Command::Abort => return Control::Abort
+        };
+        Control::Continue
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::disassembler::render;
+    use crate::object::Object;
+    use crate::error::ErrorKind;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    // run a program under a debugger, recording the instruction and depth
+    // at every pause and answering each pause with the next command
+    fn debug(source: &str, setup: impl FnOnce(&Value, &mut Vec<Breakpoint>), commands: Vec<Command>)
+        -> (Vec<(String, usize)>, Result<(), crate::error::RuntimeError>) {
+        let program = parse(source).unwrap();
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let mut commands = commands.into_iter();
+
+        let recorded = pauses.clone();
+        let mut debugger = Debugger::new(move |context: &Context| {
+            recorded.borrow_mut().push((render(context.instruction()), context.depth()));
+            commands.next().unwrap_or(Command::Continue)
+        });
+        let mut breakpoints = vec![];
+        setup(&program, &mut breakpoints);
+        for breakpoint in breakpoints {
+            debugger.add_breakpoint(breakpoint);
+        }
+
+        let mut frame = StackFrame::from_instructions(program);
+        frame.set_hook(Some(Rc::new(RefCell::new(debugger))));
+        let result = frame.run();
+        let pauses = pauses.borrow().clone();
+        (pauses, result)
+    }
+
+    #[test]
+    fn breakpoints_pause_inside_called_functions() {
+        let (pauses, result) = debug("1 { 2 add } call", |program, breakpoints| {
+            let body = program.get_list()[1].clone();
+            breakpoints.push(Breakpoint::new(body, 1));
+        }, vec![]);
+        assert!(result.is_ok());
+        assert_eq!(pauses, [("add".to_string(), 1)]);
+    }
+
+    #[test]
+    fn stepping_over_skips_calls() {
+        let (pauses, _) = debug("1 { 2 add } call 3", |program, breakpoints| {
+            breakpoints.push(Breakpoint::new(program.clone(), 1));
+        }, vec![Command::StepOver, Command::StepOver, Command::Continue]);
+        let instructions: Vec<&str> = pauses.iter().map(|(i, _)| i.as_str()).collect();
+        assert_eq!(instructions, ["{ 2 add }", "call", "3"]);
+    }
+
+    #[test]
+    fn stepping_into_and_out_of_calls() {
+        let (pauses, _) = debug("1 { 2 add } call 3", |program, breakpoints| {
+            breakpoints.push(Breakpoint::new(program.clone(), 2));
+        }, vec![Command::StepInto, Command::StepOut, Command::Continue]);
+        assert_eq!(pauses, [("call".to_string(), 0), ("2".to_string(), 1), ("3".to_string(), 0)]);
+    }
+
+    #[test]
+    fn aborting_stops_the_program() {
+        let mut debugger = Debugger::new(|_: &Context| Command::Abort);
+        debugger.pause_at_start();
+        let mut frame = StackFrame::from_instructions(parse("1 2 add").unwrap());
+        frame.set_hook(Some(Rc::new(RefCell::new(debugger))));
+        assert_eq!(frame.run().unwrap_err().kind(), ErrorKind::Aborted);
+        assert!(frame.contents().is_empty());
+    }
+}
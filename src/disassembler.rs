@@ -0,0 +1,184 @@
+// The disassembler prints a function's instructions for reading, one
+// item per line, in the assembly syntax of `parser::parse`:
+//
+//   0000  "hey jude"
+//   0001  <>
+//   0002  setattr
+//   0003  {
+//   0000    1
+//   0001    2
+//   0002    add
+//         }
+//   0004  call
+//
+// Every line starts with the item's offset in its enclosing function,
+// written as four decimal digits, followed by two spaces and two more
+// spaces of indentation per level of nesting. Function literals open
+// with `{` at their own offset, list their items with offsets starting
+// again from zero, and close with `}` on a line with a blank offset.
+// Lists are printed on a single line, instance attributes are sorted
+// by name, and instructions are printed as lowercase mnemonics, so the
+// output for a given program never changes between runs.
+//
+// With the offset column removed, the listing of anything the parser
+// can produce reads back as the same function. Values only a running
+// program can make are printed for display and can't be parsed back:
+// problems as `Kind("message", payload)`, maps as `Map{key: value}`,
+// instances with attributes as `<name:value>`, foreign functions as
+// `<foreign function>`, and NaN and the infinities by their names.
+
+use crate::value::*;
+use crate::object::*;
+
+
+// disassemble a function (or list) of instructions
+pub fn disassemble(function: &Value) -> String {
+    let mut result = String::new();
+    match function.get_type() {
+        Type::Function | Type::List => write_body(&mut result, function, 0),
+        _ => write_line(&mut result, Some(0), 0, &render(function))
+    }
+    result
+}
+
+// render a single value on one line, in assembly syntax where there is one
+pub fn render(value: &Value) -> String {
+    match value.get_type() {
+        Type::Str => escape(value.get_string()),
+        Type::Num => format!("{}", value.as_number()),
+        Type::Bool => format!("{}", value.as_bool()),
+        Type::Nothing => "none".to_string(),
+        Type::Command(i) => mnemonic(i),
+        Type::Problem(p) => match value.problem_payload() {
+            Some(payload) => format!("{:?}({}, {})", p, escape(value.get_string()), render(&payload)),
+            None => format!("{:?}({})", p, escape(value.get_string()))
+        },
+        Type::List => format!("[{}]", render_items(value)),
+        Type::Function => {
+            if value.is_foreign_function() {
+                "<foreign function>".to_string()
+            } else if value.get_list().is_empty() {
+                "{ }".to_string()
+            } else {
+                format!("{{ {} }}", render_items(value))
+            }
+        },
+        Type::Map => {
+            let entries: Vec<String> = value.get_map().iter()
+                .map(|(k, v)| format!("{}: {}", render(&k.to_object()), render(v)))
+                .collect();
+            format!("Map{{{}}}", entries.join(", "))
+        },
+        Type::Instance => {
+            let table = value.get_attributes();
+            let mut keys = table.keys();
+            keys.sort();
+
+            let attributes: Vec<String> = keys.into_iter()
+                .map(|key| format!("{}:{}", key, render(&value.get_attr(key.clone()))))
+                .collect();
+            format!("<{}>", attributes.join(", "))
+        }
+    }
+}
+
+// the lowercase name of an instruction, as accepted by the parser
+pub fn mnemonic(instruction: Instruction) -> String {
+    format!("{:?}", instruction).to_lowercase()
+}
+
+
+fn write_body(result: &mut String, function: &Value, depth: usize) {
+    for (offset, item) in function.get_list().iter().enumerate() {
+        if item.get_type() == Type::Function && !item.is_foreign_function() {
+            write_line(result, Some(offset), depth, "{");
+            write_body(result, item, depth + 1);
+            write_line(result, None, depth, "}");
+        } else {
+            write_line(result, Some(offset), depth, &render(item));
+        }
+    }
+}
+
+fn write_line(result: &mut String, offset: Option<usize>, depth: usize, text: &str) {
+    match offset {
+        Some(o) => result.push_str(&format!("{:04}  ", o)),
+        None => result.push_str("      ")
+    }
+    result.push_str(&"  ".repeat(depth));
+    result.push_str(text);
+    result.push('\n');
+}
+
+fn render_items(value: &Value) -> String {
+    value.get_list().iter()
+        .map(render)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn escape(string: &str) -> String {
+    let mut result = "\"".to_string();
+    for ch in string.chars() {
+        match ch {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            _ => result.push(ch)
+        }
+    }
+    result.push('"');
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::literals::*;
+
+    // the listing without its offset column
+    fn strip_offsets(listing: &str) -> String {
+        listing.lines()
+            .map(|line| &line[6..])
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn listings_show_offsets_and_nesting() {
+        let program = parse("\"hey jude\" <> setattr { 1 2 add } call").unwrap();
+        assert_eq!(disassemble(&program), [
+            "0000  \"hey jude\"",
+            "0001  <>",
+            "0002  setattr",
+            "0003  {",
+            "0000    1",
+            "0001    2",
+            "0002    add",
+            "      }",
+            "0004  call",
+            ""
+        ].join("\n"));
+    }
+
+    #[test]
+    fn listings_parse_back_into_the_same_program() {
+        let source = "\"a \\\"quoted\\\"\\n line\" -3.25 1E+3 none true false <> \
+                      [1 [\"x\"] { }] { { } 2 { \"x\" load mul } call } 0 while println";
+        let program = parse(source).unwrap();
+        let listing = disassemble(&program);
+        assert_eq!(parse(&strip_offsets(&listing)).unwrap(), program);
+    }
+
+    #[test]
+    fn runtime_values_are_rendered_for_display() {
+        let problem = Value::from_problem_with(Problem::ValueError, "bad", Some(string("x")));
+        assert_eq!(render(&problem), "ValueError(\"bad\", \"x\")");
+        assert!(parse(&render(&problem)).is_err());
+    }
+}
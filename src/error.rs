@@ -1,13 +1,195 @@
-use std::fmt::Debug;
-use std::process::exit;
+use std::fmt::{Display, Formatter, Result};
 
-pub fn throw<T>(s: &str, stack: Vec<T>) where T: Debug {
-    println!("==[ ERROR ]========> {}", s);
-    println!("==[ STACK TRACE ]==> {:?}", stack);
-    exit(1);
+use crate::value::Value;
+use crate::object::Object;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorKind {
+    StackUnderflow,      // an instruction popped off of an empty stack
+    MissingInstance,     // getattr / setattr found no instance on the stack
+    MissingAttribute,    // getattr / setattr was not given an attribute name
+    InvalidNumber,       // a string could not be parsed as a number
+    OutOfFuel,           // the program ran out of fuel or past its deadline
+    CallDepthExceeded,   // calls were nested too deeply
+    StackOverflow,       // a stack held too many values
+    ValueTooLarge,       // a list or string grew too large
+    Aborted,             // a hook stopped the program
+    ForeignFunction,     // a foreign function failed
+    TypeMismatch,        // a native function was given an argument of the wrong type
+    Raised,              // a program raised a value that nothing caught
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    kind: ErrorKind,             // what went wrong
+    message: String,             // a human readable description of the error
+    instruction: Option<usize>,  // the index of the instruction that failed
+    stack: Vec<Value>,           // the contents of the stack when the error occurred
+    raised: Option<Box<Value>>,  // the value a program raised, for a Raised error
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: &str) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            instruction: None,
+            stack: vec![],
+            raised: None,
+        }
+    }
+
+    // an error that carries a value for `try` to catch
+    pub fn raise(value: Value) -> Self {
+        let mut error = Self::new(ErrorKind::Raised, &value.format());
+        error.raised = Some(Box::new(value));
+        error
+    }
+
+    // attach a snapshot of the stack to the error
+    pub fn with_stack(mut self, stack: Vec<Value>) -> Self {
+        self.stack = stack;
+        self
+    }
+
+    // record the index of the failing instruction,
+    // keeping the innermost index if one is already set
+    pub fn at_instruction(mut self, index: usize) -> Self {
+        if self.instruction.is_none() {
+            self.instruction = Some(index);
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn instruction(&self) -> Option<usize> {
+        self.instruction
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn raised(&self) -> Option<&Value> {
+        self.raised.as_deref()
+    }
+
+    // whether `try` can catch the error. running out of fuel
+    // and being aborted by a hook always stop the program
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self.kind, ErrorKind::OutOfFuel | ErrorKind::Aborted)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.instruction {
+            Some(i) => write!(f, "{:?} at instruction {}: {}", self.kind, i, self.message),
+            None => write!(f, "{:?}: {}", self.kind, self.message)
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    line: usize,      // the line the error was found on, starting at 1
+    column: usize,    // the column the error was found on, starting at 1
+    message: String,  // a human readable description of the error
+}
+
+impl ParseError {
+    pub fn new(line: usize, column: usize, message: &str) -> Self {
+        Self {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
 }
 
-pub fn throw_no_stack(s: &str) {
-    println!("==[ ERROR ]========> {}", s);
-    exit(1);
-}
\ No newline at end of file
+impl std::error::Error for ParseError {}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    ForeignFunction,                // foreign functions can't be encoded
+    InvalidHeader,                  // the bytes don't start with the magic header
+    UnsupportedVersion(u16),        // the bytes were encoded by an unknown version
+    UnexpectedEnd(usize),           // the bytes ended in the middle of a value
+    InvalidTag(usize, u8),          // an unknown type, instruction or problem tag
+    InvalidConstant(usize, u32),    // a reference to a missing or mistyped constant
+    InvalidString(usize),           // a string constant that isn't valid utf-8
+    InvalidNumber(usize),           // a number constant that can't be parsed
+    InvalidKey(usize),              // a map key that isn't a number, string or list of keys
+    TooDeep(usize),                 // values nested more than `bytecode::MAX_NESTING` deep
+    TrailingBytes(usize),           // there are bytes left over after the value
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            BytecodeError::ForeignFunction => write!(f, "foreign functions can not be encoded as bytecode"),
+            BytecodeError::InvalidHeader => write!(f, "missing bytecode header"),
+            BytecodeError::UnsupportedVersion(v) => write!(f, "unsupported bytecode version {}", v),
+            BytecodeError::UnexpectedEnd(o) => write!(f, "unexpected end of bytecode at byte {}", o),
+            BytecodeError::InvalidTag(o, t) => write!(f, "invalid tag {} at byte {}", t, o),
+            BytecodeError::InvalidConstant(o, c) => write!(f, "invalid constant {} at byte {}", c, o),
+            BytecodeError::InvalidString(o) => write!(f, "invalid utf-8 string at byte {}", o),
+            BytecodeError::InvalidNumber(o) => write!(f, "invalid number at byte {}", o),
+            BytecodeError::InvalidKey(o) => write!(f, "invalid map key at byte {}", o),
+            BytecodeError::TooDeep(o) => write!(f, "values nested too deep at byte {}", o),
+            BytecodeError::TrailingBytes(o) => write!(f, "unexpected trailing bytes at byte {}", o),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_innermost_instruction_is_kept() {
+        let error = RuntimeError::new(ErrorKind::StackUnderflow, "empty")
+            .at_instruction(3)
+            .at_instruction(7);
+        assert_eq!(error.instruction(), Some(3));
+        assert_eq!(error.to_string(), "StackUnderflow at instruction 3: empty");
+    }
+
+    #[test]
+    fn errors_without_an_instruction_display_their_kind() {
+        let error = RuntimeError::new(ErrorKind::MissingInstance, "no instance");
+        assert_eq!(error.to_string(), "MissingInstance: no instance");
+    }
+}
@@ -0,0 +1,182 @@
+// Converting between numbers and strings inside a program.
+//
+// `formatnum` writes the number on top of the stack using the format
+// string below it. A format is an optional `,` to separate thousands,
+// an optional `.` and number of decimal places, and `f` for fixed or
+// `e` for scientific notation, where fixed is the default:
+//
+//   "" 1E+3 formatnum        => "1000"
+//   ",.2" 1234.5 formatnum   => "1,234.50"
+//   ".3e" 1234.5 formatnum   => "1.235e3"
+//
+// Without a number of places, numbers are written with as many digits
+// as they need. `toradix` writes an integer in a radix from 2 to 36,
+// and `parsenum` reads a number from a string the way number literals
+// are read, giving a ValueError problem if the string isn't one.
+
+use crate::value::*;
+use crate::object::*;
+use crate::literals::*;
+use crate::math::MAX_PLACES;
+
+
+enum Notation {
+    Fixed,
+    Scientific,
+}
+
+struct Format {
+    grouped: bool,
+    places: Option<usize>,
+    notation: Notation,
+}
+
+// read a format like ",.2f", or None if it isn't one
+fn parse_format(format: &str) -> Option<Format> {
+    let (grouped, rest) = match format.strip_prefix(',') {
+        Some(rest) => (true, rest),
+        None => (false, format)
+    };
+    let (rest, notation) = match rest.strip_suffix('e') {
+        Some(rest) => (rest, Notation::Scientific),
+        None => (rest.strip_suffix('f').unwrap_or(rest), Notation::Fixed)
+    };
+    let places = match rest.strip_prefix('.') {
+        Some(digits) if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+            match digits.parse::<i64>() {
+                Ok(n) if n <= MAX_PLACES => Some(n as usize),
+                _ => return None
+            }
+        },
+        Some(_) => return None,
+        None if rest.is_empty() => None,
+        None => return None
+    };
+    Some(Format {grouped, places, notation})
+}
+
+fn type_error(message: &str) -> Value {
+    Value::from_problem_with(Problem::TypeError, message, None)
+}
+
+
+// write a number with a format string
+pub fn format_number(a: Value, format: Value) -> Value {
+    if a.is_problem() {
+        return a;
+    }
+    if format.is_problem() {
+        return format;
+    }
+    if a.get_type() != Type::Num {
+        return type_error(&format!("cannot format {:?} values", a.get_type()));
+    }
+    if format.get_type() != Type::Str {
+        return type_error(&format!("the number format must be a string, not {:?}", format.get_type()));
+    }
+
+    let spec = match parse_format(format.get_string()) {
+        Some(spec) => spec,
+        None => return Value::from_problem_with(
+            Problem::ValueError,
+            &format!("invalid number format `{}`", format.get_string()),
+            Some(format.clone())
+        )
+    };
+
+    let n = a.as_number();
+    let text = match spec.notation {
+        Notation::Fixed => n.to_fixed(spec.places, spec.grouped),
+        Notation::Scientific => n.to_scientific(spec.places)
+    };
+    string(&text)
+}
+
+// write an integer in a radix from 2 to 36
+pub fn to_radix(a: Value, radix: Value) -> Value {
+    if a.is_problem() {
+        return a;
+    }
+    if radix.is_problem() {
+        return radix;
+    }
+    if a.get_type() != Type::Num {
+        return type_error(&format!("cannot toradix {:?} values", a.get_type()));
+    }
+    if radix.get_type() != Type::Num {
+        return type_error(&format!("the radix must be a number, not {:?}", radix.get_type()));
+    }
+
+    let base = match radix.as_number().to_u32() {
+        Some(base) if (2..=36).contains(&base) => base,
+        _ => return Value::from_problem_with(
+            Problem::ValueError,
+            &format!("the radix must be a whole number from 2 to 36, not {}", radix.as_number()),
+            Some(radix.clone())
+        )
+    };
+    match a.as_number().to_radix(base) {
+        Some(text) => string(&text),
+        None => type_error("cannot toradix numbers that aren't integers")
+    }
+}
+
+// read a number from a string, ignoring surrounding whitespace
+pub fn parse_number(a: Value) -> Value {
+    if a.is_problem() {
+        return a;
+    }
+    if a.get_type() != Type::Str {
+        return type_error(&format!("cannot parse {:?} values as numbers", a.get_type()));
+    }
+    num(a.get_string().trim())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(n: &str, spec: &str) -> Value {
+        format_number(num(n), string(spec))
+    }
+
+    #[test]
+    fn numbers_are_written_with_a_format() {
+        assert_eq!(format("1E+3", ""), string("1000"));
+        assert_eq!(format("1234.5", ",.2"), string("1,234.50"));
+        assert_eq!(format("1234.5", ".3e"), string("1.235e3"));
+        assert_eq!(format("-1234567", ",f"), string("-1,234,567"));
+        assert_eq!(format("2.50", ""), string("2.5"));
+        assert_eq!(format("0.125", ".2"), string("0.13"));
+    }
+
+    #[test]
+    fn bad_formats_are_problems() {
+        assert_eq!(format("1", ".x").get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(format("1", "2").get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(format("1", ".100000").get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(format_number(num("1"), num("2")).get_type(), Type::Problem(Problem::TypeError));
+        assert_eq!(format_number(string("1"), string("")).get_type(), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn integers_are_written_in_a_radix() {
+        assert_eq!(to_radix(num("255"), num("16")), string("ff"));
+        assert_eq!(to_radix(num("-5"), num("2")), string("-101"));
+        assert_eq!(to_radix(num("36893488147419103232"), num("16")), string("20000000000000000"));
+        assert_eq!(to_radix(num("1"), num("37")).get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(to_radix(num("1.5"), num("10")).get_type(), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn strings_are_parsed_like_literals() {
+        assert_eq!(parse_number(string(" 42 ")), num("42"));
+        assert_eq!(parse_number(string("-1.5E+2")), num("-150"));
+        assert_eq!(parse_number(string("123456789012345678901234567890")).as_number().to_string(),
+            "123456789012345678901234567890");
+        assert_eq!(parse_number(string("12abc")).get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(parse_number(string("")).get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(parse_number(num("1")).get_type(), Type::Problem(Problem::TypeError));
+    }
+}
@@ -1,9 +1,20 @@
 pub mod number;
 pub mod stack;
 pub mod table;
+pub mod map;
+pub mod math;
+pub mod format;
 pub mod value;
 pub mod error;
 pub mod object;
 pub mod literals;
+pub mod parser;
+pub mod disassembler;
+pub mod bytecode;
+pub mod debugger;
+pub mod registry;
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod serialize;
 
-use number::*;
\ No newline at end of file
+pub use error::{ErrorKind, RuntimeError, ParseError, BytecodeError};
\ No newline at end of file
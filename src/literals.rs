@@ -2,6 +2,7 @@
 use std::str::FromStr;
 use crate::value::*;
 use crate::object::*;
+use crate::error::RuntimeError;
 
 #[allow(unused_macros)]
 #[macro_export] macro_rules! inline_foreign_function {
@@ -55,8 +56,20 @@ pub fn string(s: &str) -> Value {
     Value::from_str(s)
 }
 
+// an invalid number literal becomes a ValueError problem
 pub fn num(n: &str) -> Value {
-    Value::from_number(string_to_number(n.to_string()))
+    match string_to_number(n.to_string()) {
+        Ok(number) => Value::from_number(number),
+        Err(_) => Value::from_problem_with(
+            Problem::ValueError,
+            &format!("invalid number `{}`", n),
+            Some(string(n))
+        )
+    }
+}
+
+pub fn boolean(b: bool) -> Value {
+    Value::from_bool(b)
 }
 
 pub fn none() -> Value {
@@ -87,4 +100,9 @@ pub fn ins(i: Instruction) -> Value {
 
 pub fn foreign_function(fun: fn(Value) -> Value) -> Value {
     Value::from_foreign_function(fun)
+}
+
+// a foreign function that can capture state, and fail
+pub fn foreign_closure<F: Fn(Value) -> Result<Value, RuntimeError> + 'static>(closure: F) -> Value {
+    Value::from_closure(closure)
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use crate::object::*;
+use crate::number::Number;
+
+
+// a value that can be used as the key of a map: a boolean, a number
+// other than NaN, a string, or a list of keys. keys are ordered with
+// booleans first, then numbers, strings and lists, which is the
+// order maps iterate in
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Bool(bool),
+    Num(Number),
+    Str(String),
+    List(Vec<Key>),
+}
+
+impl Key {
+    // the key for an object, or None if it can't be used as a key
+    pub fn from_object<T: Object>(object: &T) -> Option<Self> {
+        match object.get_type() {
+            Type::Bool => Some(Key::Bool(object.as_bool())),
+            Type::Num if object.as_number().is_nan() => None,
+            Type::Num => Some(Key::Num(object.as_number())),
+            Type::Str => Some(Key::Str(object.get_string().to_string())),
+            Type::List => object.get_list().iter()
+                .map(Key::from_object)
+                .collect::<Option<Vec<Key>>>()
+                .map(Key::List),
+            _ => None
+        }
+    }
+
+    pub fn to_object<T: Object>(&self) -> T {
+        match self {
+            Key::Bool(b) => T::from_bool(*b),
+            Key::Num(n) => T::from_number(n.clone()),
+            Key::Str(s) => T::from_str(s),
+            Key::List(l) => T::from_vector(l.iter().map(Key::to_object).collect())
+        }
+    }
+}
+
+
+// a map from keys to values, iterated in the order of its keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map<T> {
+    entries: BTreeMap<Key, T>,
+    size: usize     // one cell for each key plus the size of its value
+}
+
+impl<T: Object> Map<T> {
+    // set the value of a key, returning its old value
+    pub fn insert(&mut self, key: Key, value: T) -> Option<T> {
+        self.size += 1 + value.size();
+        let old = self.entries.insert(key, value);
+        if let Some(o) = &old {
+            self.size -= 1 + o.size();
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<T> {
+        let old = self.entries.remove(key);
+        if let Some(o) = &old {
+            self.size -= 1 + o.size();
+        }
+        old
+    }
+
+    // the `Object::size` cells taken by the entries, kept up to date
+    // as they change so that checking it doesn't walk the whole map
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl<T: Clone> Map<T> {
+    pub fn new() -> Self {
+        Self {entries: BTreeMap::new(), size: 0}
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&T> {
+        self.entries.get(key)
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&Key, &T)> {
+        self.entries.iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item=&Key> {
+        self.entries.keys()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item=&T> {
+        self.entries.values()
+    }
+}
+
+impl<T: Clone> Default for Map<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+    use crate::literals::*;
+
+    fn key(s: &str) -> Key {
+        Key::Str(s.to_string())
+    }
+
+    #[test]
+    fn the_size_follows_inserts_and_removes() {
+        let mut map = Map::new();
+        map.insert(key("a"), num("1"));
+        map.insert(key("b"), list(&[num("1"), num("2")]));
+        assert_eq!(map.size(), 2 + num("1").size() + list(&[num("1"), num("2")]).size());
+
+        map.insert(key("b"), num("3"));
+        assert_eq!(map.size(), 2 + 2 * num("1").size());
+
+        map.remove(&key("a"));
+        map.remove(&key("missing"));
+        assert_eq!(map.size(), 1 + num("3").size());
+        assert_eq!(Value::from_map(map).size(), 1 + 1 + num("3").size());
+    }
+
+    #[test]
+    fn keys_are_ordered_by_type_then_value() {
+        let mut map: Map<Value> = Map::new();
+        map.insert(Key::from_object(&string("b")).unwrap(), none());
+        map.insert(Key::from_object(&num("2")).unwrap(), none());
+        map.insert(Key::from_object(&boolean(true)).unwrap(), none());
+        map.insert(Key::from_object(&num("-1")).unwrap(), none());
+        let keys: Vec<Value> = map.keys().map(Key::to_object).collect();
+        assert_eq!(keys, [boolean(true), num("-1"), num("2"), string("b")]);
+    }
+
+    #[test]
+    fn large_integers_and_nearby_decimals_are_different_keys() {
+        let mut map: Map<Value> = Map::new();
+        for n in ["10000000000000000000000000000000000000001", "1E+40", "99999999999999999999", "99999999999999999999.5"] {
+            map.insert(Key::from_object(&num(n)).unwrap(), string(n));
+        }
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&Key::from_object(&num("1E+40")).unwrap()), Some(&string("1E+40")));
+    }
+
+    #[test]
+    fn nan_and_functions_are_not_keys() {
+        assert_eq!(Key::from_object(&num("NaN")), None);
+        assert_eq!(Key::from_object(&fun(&[])), None);
+        assert_eq!(Key::from_object(&list(&[num("1"), fun(&[])])), None);
+    }
+}
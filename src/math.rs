@@ -0,0 +1,256 @@
+// The math instructions, beyond the arithmetic operators on values.
+//
+// Like the arithmetic operators, binary instructions pop the top value
+// first and apply the operator with it on the left, so `2 10 pow` is
+// 10 to the power of 2. The rounding instructions take the number of
+// decimal places below the number being rounded:
+//
+//   2 3.14159 round    => 3.14
+//   -2 1234 floor      => 1200
+//
+// Problem operands pass through, and operands of the wrong type give
+// a TypeError problem rather than stopping the program. NaN and the
+// infinities give whatever the decimal backend defines for them,
+// such as NaN for `-1 sqrt` and -Infinity for `0 ln`, except that
+// dividing by zero gives a DivisionByZero problem.
+
+use crate::value::*;
+use crate::object::*;
+use crate::number::Number;
+use num_traits::ToPrimitive;
+
+
+// the most places a number can be rounded to, either way
+pub(crate) const MAX_PLACES: i64 = 10000;
+
+// the furthest an integer can be shifted left, in bits
+const MAX_SHIFT: usize = 1 << 20;
+
+
+fn type_error(message: &str) -> Value {
+    Value::from_problem_with(Problem::TypeError, message, None)
+}
+
+fn number_problem(a: &Value, operation: &str) -> Option<Value> {
+    if a.is_problem() {
+        return Some(a.clone());
+    }
+    if a.get_type() != Type::Num {
+        return Some(type_error(&format!("cannot {} {:?} values", operation, a.get_type())));
+    }
+    None
+}
+
+// a whole number argument in a range, like a number of places
+fn whole_argument(a: &Value, limit: i64, what: &str) -> Result<i64, Value> {
+    if a.is_problem() {
+        return Err(a.clone());
+    }
+    if a.get_type() != Type::Num {
+        return Err(type_error(&format!("{} must be a number, not {:?}", what, a.get_type())));
+    }
+    match a.as_number().to_i64() {
+        Some(n) if -limit <= n && n <= limit => Ok(n),
+        _ => Err(Value::from_problem_with(
+            Problem::ValueError,
+            &format!("{} must be a whole number from {} to {}, not {}", what, -limit, limit, a.as_number()),
+            Some(a.clone())
+        ))
+    }
+}
+
+
+// apply a one operand math instruction
+pub fn unary(instruction: Instruction, a: Value) -> Value {
+    let operation = format!("{:?}", instruction).to_lowercase();
+    if let Some(problem) = number_problem(&a, &format!("take the {} of", operation)) {
+        return problem;
+    }
+
+    let n = a.as_number();
+    Value::from_number(match instruction {
+        Instruction::Abs => n.abs(),
+        Instruction::Neg => -n,
+        Instruction::Sqrt => n.sqrt(),
+        Instruction::Exp => n.exp(),
+        Instruction::Ln => n.ln(),
+        Instruction::Log10 => n.log10(),
+        Instruction::Sin => n.sin(),
+        Instruction::Cos => n.cos(),
+        Instruction::Tan => n.tan(),
+        Instruction::Asin => n.asin(),
+        Instruction::Acos => n.acos(),
+        Instruction::Atan => n.atan(),
+        _ => unreachable!("{:?} is not a unary math instruction", instruction)
+    })
+}
+
+// apply a two operand math instruction, with `a` on the left
+pub fn binary(instruction: Instruction, a: Value, b: Value) -> Value {
+    let operation = format!("{:?}", instruction).to_lowercase();
+    if let Some(problem) = operand_problem(&a, &b, &operation, &[Type::Num]) {
+        return problem;
+    }
+
+    if instruction == Instruction::IntDiv {
+        if let Some(problem) = zero_divisor(&a, &b) {
+            return problem;
+        }
+    }
+
+    let (x, y) = (a.as_number(), b.as_number());
+    let result = match instruction {
+        Instruction::Pow => Some(x.pow(&y)),
+        Instruction::IntDiv => Some(x.div_floor(&y)),
+        Instruction::Min => Some(Number::min(&x, &y)),
+        Instruction::Max => Some(Number::max(&x, &y)),
+        Instruction::BitAnd => x.bitand(&y),
+        Instruction::BitOr => x.bitor(&y),
+        Instruction::BitXor => x.bitxor(&y),
+        _ => unreachable!("{:?} is not a binary math instruction", instruction)
+    };
+
+    match result {
+        Some(n) => Value::from_number(n),
+        None => type_error(&format!("cannot {} numbers that aren't integers", operation))
+    }
+}
+
+// round `a` to a number of decimal places
+pub fn rounding(instruction: Instruction, a: Value, places: Value) -> Value {
+    let operation = format!("{:?}", instruction).to_lowercase();
+    if let Some(problem) = number_problem(&a, &operation) {
+        return problem;
+    }
+    let places = match whole_argument(&places, MAX_PLACES, "the number of places") {
+        Ok(p) => p,
+        Err(problem) => return problem
+    };
+
+    let n = a.as_number();
+    Value::from_number(match instruction {
+        Instruction::Floor => n.floor(places),
+        Instruction::Ceil => n.ceil(places),
+        Instruction::Round => n.round(places),
+        Instruction::Trunc => n.trunc(places),
+        _ => unreachable!("{:?} is not a rounding instruction", instruction)
+    })
+}
+
+// ask whether `a` is NaN or infinite. values that aren't numbers are neither
+pub fn query(instruction: Instruction, a: Value) -> Value {
+    if a.is_problem() {
+        return a;
+    }
+    let is_number = a.get_type() == Type::Num;
+    Value::from_bool(is_number && match instruction {
+        Instruction::IsNan => a.as_number().is_nan(),
+        Instruction::IsInfinite => a.as_number().is_infinite(),
+        _ => unreachable!("{:?} is not a number query", instruction)
+    })
+}
+
+// shift the integer `a` by a number of bits
+pub fn shift(instruction: Instruction, a: Value, amount: Value) -> Value {
+    let operation = format!("{:?}", instruction).to_lowercase();
+    if let Some(problem) = number_problem(&a, &operation) {
+        return problem;
+    }
+    if let Some(problem) = number_problem(&amount, "shift by") {
+        return problem;
+    }
+
+    let bits = match amount.as_number().to_bigint() {
+        Some(bits) if bits >= 0.into() => bits.to_usize().unwrap_or(usize::MAX),
+        _ => return Value::from_problem_with(
+            Problem::ValueError,
+            &format!("cannot shift by {}", amount.as_number()),
+            Some(amount.clone())
+        )
+    };
+    if instruction == Instruction::Shl && bits > MAX_SHIFT {
+        return Value::from_problem_with(
+            Problem::Overflow,
+            &format!("cannot shift left by more than {} bits", MAX_SHIFT),
+            Some(amount.clone())
+        );
+    }
+
+    let n = a.as_number();
+    let result = match instruction {
+        Instruction::Shl => n.shl(bits),
+        Instruction::Shr => n.shr(bits),
+        _ => unreachable!("{:?} is not a shift instruction", instruction)
+    };
+    match result {
+        Some(n) => Value::from_number(n),
+        None => type_error(&format!("cannot {} numbers that aren't integers", operation))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::literals::*;
+
+    fn kind(value: &Value) -> Type {
+        value.get_type()
+    }
+
+    #[test]
+    fn unary_instructions_follow_the_decimal_rules() {
+        assert_eq!(unary(Instruction::Abs, num("-2.5")), num("2.5"));
+        assert_eq!(unary(Instruction::Neg, num("3")), num("-3"));
+        assert_eq!(unary(Instruction::Sqrt, num("16")), num("4"));
+        assert!(unary(Instruction::Sqrt, num("-1")).as_number().is_nan());
+        assert_eq!(unary(Instruction::Ln, num("0")).as_number().to_string(), "-Infinity");
+        assert_eq!(kind(&unary(Instruction::Sin, string("a"))), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn binary_instructions_take_the_top_value_on_the_left() {
+        assert_eq!(binary(Instruction::Pow, num("10"), num("2")), num("100"));
+        assert_eq!(binary(Instruction::IntDiv, num("-7"), num("2")), num("-4"));
+        assert_eq!(binary(Instruction::Min, num("1"), num("2")), num("1"));
+        assert_eq!(binary(Instruction::Max, num("1"), num("2")), num("2"));
+        assert_eq!(binary(Instruction::BitXor, num("6"), num("3")), num("5"));
+        assert_eq!(kind(&binary(Instruction::IntDiv, num("1"), num("0"))), Type::Problem(Problem::DivisionByZero));
+        assert_eq!(kind(&binary(Instruction::BitAnd, num("1.5"), num("1"))), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn rounding_takes_a_number_of_places() {
+        assert_eq!(rounding(Instruction::Round, num("3.14159"), num("2")), num("3.14"));
+        assert_eq!(rounding(Instruction::Floor, num("1234"), num("-2")), num("1200"));
+        assert_eq!(rounding(Instruction::Ceil, num("-1.5"), num("0")), num("-1"));
+        assert_eq!(rounding(Instruction::Trunc, num("-1.5"), num("0")), num("-1"));
+        assert_eq!(rounding(Instruction::Round, num("-2.5"), num("0")), num("-3"));
+        assert_eq!(kind(&rounding(Instruction::Round, num("1"), num("0.5"))), Type::Problem(Problem::ValueError));
+        assert_eq!(kind(&rounding(Instruction::Round, num("1"), num("100000"))), Type::Problem(Problem::ValueError));
+    }
+
+    #[test]
+    fn shifts_work_on_integers() {
+        assert_eq!(shift(Instruction::Shl, num("1"), num("100")).as_number().to_string(), "1267650600228229401496703205376");
+        assert_eq!(shift(Instruction::Shr, num("-1"), num("5")), num("-1"));
+        assert_eq!(kind(&shift(Instruction::Shl, num("1"), num("-1"))), Type::Problem(Problem::ValueError));
+        assert_eq!(kind(&shift(Instruction::Shl, num("1"), num("10000000"))), Type::Problem(Problem::Overflow));
+        assert_eq!(kind(&shift(Instruction::Shr, num("0.5"), num("1"))), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn queries_are_false_for_values_that_are_not_numbers() {
+        assert_eq!(query(Instruction::IsNan, num("NaN")), boolean(true));
+        assert_eq!(query(Instruction::IsInfinite, num("-Infinity")), boolean(true));
+        assert_eq!(query(Instruction::IsNan, string("NaN")), boolean(false));
+    }
+
+    #[test]
+    fn problems_pass_through() {
+        let problem = Value::from_problem(Problem::KeyError);
+        assert_eq!(kind(&unary(Instruction::Abs, problem.clone())), Type::Problem(Problem::KeyError));
+        assert_eq!(kind(&binary(Instruction::Pow, num("1"), problem.clone())), Type::Problem(Problem::KeyError));
+        assert_eq!(kind(&rounding(Instruction::Round, num("1"), problem)), Type::Problem(Problem::KeyError));
+    }
+}
@@ -1,9 +1,12 @@
 use std::str::FromStr;
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter, Result};
-use std::ops::{Add, Sub, Mul, Div, Rem};
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg};
 pub use decimal::*;
 // use bigdecimal::*;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero, Signed};
 
 use crate::error::*;
 
@@ -12,98 +15,637 @@ use crate::error::*;
 // type BackendNumber = f64;
 type BackendNumber = d128;
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+// the largest integer, in bits, that a power is computed exactly for.
+// bigger powers are computed in decimal, which rounds them
+const MAX_EXACT_BITS: u64 = 1 << 20;
+
+const E: &str = "2.718281828459045235360287471352662";
+
+// a number is either an exact integer of any size or a decimal.
+// integers that fit in an i64 are kept as one, and larger ones as a BigInt
+#[derive(Debug, Clone)]
+enum Backend {
+    Small(i64),
+    Big(BigInt),
+    Decimal(BackendNumber),
+}
+
+// arithmetic on two integers gives an exact integer, except division
+// that leaves a remainder. anything involving a decimal is done in
+// decimal, so the integer is rounded to 34 significant digits first
+#[derive(Debug, Clone)]
 pub struct Number {
-    number: BackendNumber,
+    number: Backend,
+}
+
+// in rust, numbers are totally ordered: NaN sorts after every other
+// number, including Infinity, and all NaNs are the same. programs
+// compare with `numeric_cmp` instead, where NaN equals nothing
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
 }
 
 impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Number {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.number.partial_cmp(&other.number).unwrap()
+        match (self.is_nan(), other.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.numeric_cmp(other).unwrap_or(Ordering::Equal)
+        }
     }
 }
 
 
-fn string_to_backend_number(s: String) -> BackendNumber {
-    match BackendNumber::from_str(&s) {
-        Ok(k) => k,
-        Err(_) => {
-            throw_no_stack(&format!("Invalid number: {}", s));
-            BackendNumber::from_str("0").unwrap()
-        }
+fn string_to_backend_number(s: &str) -> std::result::Result<BackendNumber, RuntimeError> {
+    // the backend never fails to parse, it produces NaN and
+    // flags a conversion syntax error in the decimal context
+    BackendNumber::set_status(Status::empty());
+    let number = BackendNumber::from_str(s).unwrap_or_default();
+    if BackendNumber::get_status().contains(Status::CONVERSION_SYNTAX) {
+        return Err(RuntimeError::new(
+            ErrorKind::InvalidNumber,
+            &format!("Invalid number: {}", s)
+        ));
     }
+    Ok(number)
     // match s.parse::<BackendNumber>() {
     //     Ok(n) => n,
     //     Err(_) => 0 as BackendNumber
     // }
 }
 
-fn backend_number_to_i32(n: &BackendNumber) -> i32 {
-    // println!("num to i32");
-    // match n.to_usize() {
-    //     Some(u) => u,
-    //     None => 0 as usize
-    // }
-    // match n.to_integer().to_i32() {
-    //     Some(u) => u,
-    //     None => 0
-    // }
-    return (*n).into()
-    // return (*n) as i32
+// integers are written as digits with an optional sign,
+// without a decimal point or exponent
+fn is_integer_literal(s: &str) -> bool {
+    let digits = s.strip_prefix(|c| c == '+' || c == '-').unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
 }
 
-fn backend_number_to_usize(n: &BackendNumber) -> usize {
-    // match n.to_usize() {
-    //     Some(u) => u,
-    //     None => 0 as usize
-    // }
-    // match n.to_integer().to_usize() {
-    //     Some(u) => u,
-    //     None => 0 as usize
-    // }
-    return backend_number_to_i32(n) as usize
+// run an operator on two numbers: exactly on integers if `small` or
+// `big` gives a result, otherwise on their decimal values
+fn operate(
+    lhs: Number,
+    rhs: Number,
+    small: fn(i64, i64) -> Option<i64>,
+    big: fn(&BigInt, &BigInt) -> Option<BigInt>,
+    decimal: fn(BackendNumber, BackendNumber) -> BackendNumber
+) -> Number {
+    if let (Backend::Small(a), Backend::Small(b)) = (&lhs.number, &rhs.number) {
+        if let Some(n) = small(*a, *b) {
+            return Number::from(n);
+        }
+    }
+
+    if let (Some(a), Some(b)) = (lhs.integer(), rhs.integer()) {
+        if let Some(n) = big(&a, &b) {
+            return Number::from(n);
+        }
+    }
+
+    Number::from_decimal(decimal(lhs.to_decimal(), rhs.to_decimal()))
 }
 
-fn backend_number_to_char(n: &BackendNumber) -> char {
-    // // match n.to_u8() {
-    // //     Some(u) => u as char,
-    // //     None => 0 as u8 as char
-    // // }
-    // match n.to_integer().to_u8() {
-    //     Some(u) => u as char,
-    //     None => 0 as u8 as char
-    // }
-    // // n as i32 as u8 as char
-    return backend_number_to_i32(n) as u8 as char
+fn nan() -> BackendNumber {
+    BackendNumber::from_str("NaN").unwrap_or_default()
+}
+
+// a finite decimal as its sign, its significant digits and the power
+// of ten they are multiplied by. the reduced form has no trailing zeros,
+// and has an exponent only if it is too long to write without one
+fn decimal_parts(d: BackendNumber) -> Option<(bool, String, i64)> {
+    if !d.is_finite() {
+        return None;
+    }
+
+    let text = d.reduce().to_string();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(t) => (true, t),
+        None => (false, &text[..])
+    };
+    let (mantissa, exponent) = match text.find('E') {
+        Some(i) => (&text[..i], text[i + 1..].parse::<i64>().ok()?),
+        None => (text, 0)
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, "")
+    };
+
+    let digits = format!("{}{}", whole, fraction).trim_start_matches('0').to_string();
+    if digits.is_empty() {
+        return Some((negative, "0".to_string(), 0));
+    }
+    Some((negative, digits, exponent - fraction.len() as i64))
+}
+
+// the exact value of a decimal without a fraction
+fn decimal_to_bigint(d: BackendNumber) -> Option<BigInt> {
+    let (negative, digits, exponent) = decimal_parts(d)?;
+    if exponent < 0 {
+        return None;
+    }
+    let magnitude = digits.parse::<BigInt>().ok()? * BigInt::from(10).pow(exponent as u32);
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+// compare a big integer with a decimal exactly, scaling one of them by the
+// decimal's exponent instead of rounding the integer to the decimal's digits
+fn big_decimal_cmp(n: &BigInt, d: BackendNumber) -> Option<Ordering> {
+    if d.is_nan() {
+        return None;
+    }
+    if d.is_infinite() {
+        return Some(if d.is_negative() { Ordering::Greater } else { Ordering::Less });
+    }
+
+    let (negative, digits, exponent) = decimal_parts(d)?;
+    let magnitude = digits.parse::<BigInt>().ok()?;
+    let decimal = if negative { -magnitude } else { magnitude };
+    let scale = BigInt::from(10).pow(exponent.unsigned_abs() as u32);
+    if exponent >= 0 {
+        Some(n.cmp(&(decimal * scale)))
+    } else {
+        Some((n * scale).cmp(&decimal))
+    }
+}
+
+// put a comma between each group of three digits before the point
+fn group_thousands(text: &str) -> String {
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(t) => ("-", t),
+        None => ("", text)
+    };
+    let point = text.find('.').unwrap_or(text.len());
+    let (whole, fraction) = text.split_at(point);
+
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+// which way rounding to a number of places goes
+#[derive(Clone, Copy)]
+enum Direction {
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+}
+
+impl Direction {
+    // the integer a number goes to, given its floor and how its
+    // fraction above the floor compares with a half
+    fn settle(self, floor: BigInt, exact: bool, half: Ordering) -> BigInt {
+        if exact {
+            return floor;
+        }
+        let up = match self {
+            Direction::Floor => false,
+            Direction::Ceil => true,
+            Direction::Trunc => floor.is_negative(),
+            // halves round away from zero
+            Direction::Round => match half {
+                Ordering::Less => false,
+                Ordering::Greater => true,
+                Ordering::Equal => !floor.is_negative()
+            }
+        };
+        if up { floor + 1 } else { floor }
+    }
+}
+
+
+// the conversions to rust integers, which give None if the number
+// isn't an integer or doesn't fit
+macro_rules! checked_conversion {
+    ($($name:ident $type:ty),*) => {
+        $(
+            pub fn $name(&self) -> Option<$type> {
+                match &self.number {
+                    Backend::Small(i) => i.$name(),
+                    _ => self.to_bigint()?.$name()
+                }
+            }
+        )*
+    };
 }
 
 
 impl Number {
-    fn from_big_rational(number: BackendNumber) -> Self {
-        Self {number}
+    fn from_decimal(number: BackendNumber) -> Self {
+        Self {number: Backend::Decimal(number)}
     }
 
-    pub fn from_str(s: &str) -> Self {
-        let number = string_to_backend_number(s.to_string());
+    // the value of an exact integer, but not of a decimal
+    fn integer(&self) -> Option<BigInt> {
+        match &self.number {
+            Backend::Small(i) => Some(BigInt::from(*i)),
+            Backend::Big(n) => Some(n.clone()),
+            Backend::Decimal(_) => None
+        }
+    }
 
-        Self {number}
+    // the value as a decimal, rounding integers with too many digits
+    fn to_decimal(&self) -> BackendNumber {
+        match &self.number {
+            Backend::Small(i) => BackendNumber::from(*i),
+            Backend::Big(n) => BackendNumber::from_str(&n.to_string()).unwrap_or_default(),
+            Backend::Decimal(d) => *d
+        }
     }
 
-    pub fn to_i32(&self) -> i32 {
-        backend_number_to_i32(&self.number)
+    // is this number held as an exact integer, rather than a decimal?
+    pub fn is_integer(&self) -> bool {
+        !matches!(self.number, Backend::Decimal(_))
     }
 
-    pub fn to_usize(&self) -> usize {
-        backend_number_to_usize(&self.number)
+    // the exact value, if this is an integer or a decimal without a fraction
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match &self.number {
+            Backend::Decimal(d) => decimal_to_bigint(*d),
+            _ => self.integer()
+        }
     }
 
+    checked_conversion!(
+        to_i32 i32, to_i64 i64, to_i128 i128,
+        to_u32 u32, to_u64 u64, to_u128 u128, to_usize usize
+    );
+
+    // the character with this code, or U+FFFD
+    // if it isn't the code of a unicode scalar value
     pub fn to_char(&self) -> char {
-        backend_number_to_char(&self.number)
+        self.to_u32()
+            .and_then(std::char::from_u32)
+            .unwrap_or(std::char::REPLACEMENT_CHARACTER)
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match self.number {
+            Backend::Decimal(d) => d.is_nan(),
+            _ => false
+        }
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        match self.number {
+            Backend::Decimal(d) => d.is_infinite(),
+            _ => false
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match &self.number {
+            Backend::Small(i) => *i == 0,
+            Backend::Big(_) => false,
+            Backend::Decimal(d) => d.is_zero()
+        }
+    }
+
+    // compare by the decimal rules, where NaN is unordered
+    pub fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (&self.number, &other.number) {
+            (Backend::Small(a), Backend::Small(b)) => Some(a.cmp(b)),
+            (Backend::Big(a), Backend::Decimal(d)) => big_decimal_cmp(a, *d),
+            (Backend::Decimal(d), Backend::Big(b)) => big_decimal_cmp(b, *d).map(Ordering::reverse),
+            (Backend::Decimal(_), _) | (_, Backend::Decimal(_)) => {
+                self.to_decimal().partial_cmp(&other.to_decimal())
+            },
+            _ => self.integer().partial_cmp(&other.integer())
+        }
+    }
+
+    // the sign, significant digits and exponent of a finite number
+    fn parts(&self) -> Option<(bool, String, i64)> {
+        match &self.number {
+            Backend::Decimal(d) => decimal_parts(*d),
+            _ => {
+                let n = self.integer()?;
+                Some((n.is_negative(), n.magnitude().to_string(), 0))
+            }
+        }
+    }
+
+    // the number written out in full without an exponent or trailing
+    // zeros, like 1000 for 1E+3. NaN and infinities are written as usual
+    pub fn to_plain_string(&self) -> String {
+        let (negative, digits, exponent) = match self.parts() {
+            Some(parts) => parts,
+            None => return self.to_string()
+        };
+
+        let body = if exponent >= 0 {
+            format!("{}{}", digits, "0".repeat(exponent as usize))
+        } else {
+            let point = digits.len() as i64 + exponent;
+            if point > 0 {
+                let (whole, fraction) = digits.split_at(point as usize);
+                format!("{}.{}", whole, fraction)
+            } else {
+                format!("0.{}{}", "0".repeat(-point as usize), digits)
+            }
+        };
+        let sign = if negative && digits != "0" { "-" } else { "" };
+        format!("{}{}", sign, body)
+    }
+
+    // the number without an exponent, rounded to exactly this many
+    // decimal places if there are some, with halves rounded away from zero
+    pub fn to_fixed(&self, places: Option<usize>, grouped: bool) -> String {
+        if self.parts().is_none() {
+            return self.to_string();
+        }
+        let mut text = match places {
+            Some(places) => self.round(places as i64).to_plain_string(),
+            None => self.to_plain_string()
+        };
+
+        let decimals = text.find('.').map_or(0, |i| text.len() - i - 1);
+        let places = places.unwrap_or(decimals);
+        if places > decimals {
+            if decimals == 0 {
+                text.push('.');
+            }
+            text.push_str(&"0".repeat(places - decimals));
+        }
+        if grouped { group_thousands(&text) } else { text }
     }
 
-    pub fn to_u128(&self) -> u128 {
-        backend_number_to_i32(&self.number) as u128
+    // the number as one digit before the point and an exponent, like 1.5e3.
+    // with a precision it has exactly that many digits after the point,
+    // and otherwise as many as it needs
+    pub fn to_scientific(&self, precision: Option<usize>) -> String {
+        let (negative, digits, exponent) = match self.parts() {
+            Some(parts) => parts,
+            None => return self.to_string()
+        };
+
+        let trimmed = digits.trim_end_matches('0');
+        let (mut digits, mut power) = match trimmed.len() {
+            0 => ("0".to_string(), 0),
+            n => (trimmed.to_string(), exponent + (digits.len() - n) as i64 + n as i64 - 1)
+        };
+        let sign = if negative && digits != "0" { "-" } else { "" };
+
+        if let Some(precision) = precision {
+            if digits.len() > precision + 1 {
+                // round the digits as an integer, then drop the zeros
+                // rounding left. rounding up can carry into a new digit
+                let dropped = digits.len() - precision - 1;
+                let whole = Self::from(digits.parse::<BigInt>().unwrap_or_default());
+                digits = whole.round(-(dropped as i64)).to_string();
+                digits.truncate(digits.len() - dropped);
+                if digits.len() > precision + 1 {
+                    digits.truncate(precision + 1);
+                    power += 1;
+                }
+            }
+            digits.push_str(&"0".repeat(precision + 1 - digits.len()));
+        }
+
+        let (first, rest) = digits.split_at(1);
+        match rest {
+            "" => format!("{}{}e{}", sign, first, power),
+            _ => format!("{}{}.{}e{}", sign, first, rest, power)
+        }
+    }
+
+    // an integer's digits in a radix from 2 to 36, using lowercase letters
+    pub fn to_radix(&self, radix: u32) -> Option<String> {
+        Some(self.to_bigint()?.to_str_radix(radix))
+    }
+
+    // the nearest f64, which may lose precision
+    pub fn to_f64(&self) -> f64 {
+        match &self.number {
+            Backend::Small(i) => *i as f64,
+            Backend::Big(n) => n.to_f64().unwrap_or(f64::NAN),
+            Backend::Decimal(d) => d.to_string().parse().unwrap_or(f64::NAN)
+        }
+    }
+
+    // the nearest number to an f64. infinities and NaN stay as they are
+    pub fn from_f64(n: f64) -> Self {
+        Self::from_decimal(
+            BackendNumber::from_str(&n.to_string()).unwrap_or_else(|_| nan())
+        )
+    }
+
+    // exact for an integer raised to a non-negative integer, unless
+    // the result would be enormous
+    pub fn pow(&self, exponent: &Self) -> Self {
+        if let (Some(base), Some(e)) = (self.integer(), exponent.to_u32()) {
+            if exponent.is_integer() && base.bits().saturating_mul(e as u64) <= MAX_EXACT_BITS {
+                return Self::from(base.pow(e));
+            }
+        }
+        Self::from_decimal(self.to_decimal().pow(exponent.to_decimal()))
+    }
+
+    // the quotient rounded down, as an integer unless it is NaN or infinite
+    pub fn div_floor(&self, rhs: &Self) -> Self {
+        let quotient = operate(
+            self.clone(),
+            rhs.clone(),
+            |a, b| if b == 0 || (a == i64::MIN && b == -1) { None } else { Some(Integer::div_floor(&a, &b)) },
+            |a, b| if b.is_zero() { None } else { Some(Integer::div_floor(a, b)) },
+            |a, b| a / b
+        );
+        quotient.floor(0)
+    }
+
+    pub fn abs(&self) -> Self {
+        match &self.number {
+            Backend::Small(i) => match i.checked_abs() {
+                Some(n) => Self::from(n),
+                None => Self::from(BigInt::from(*i).abs())
+            },
+            Backend::Big(n) => Self::from(n.abs()),
+            Backend::Decimal(d) => Self::from_decimal(d.abs())
+        }
+    }
+
+    // round to a number of decimal places, or to a multiple of
+    // a power of ten if places is negative. rounding to no places
+    // or fewer gives an integer, and NaN and infinities stay as they are
+    fn round_to(&self, places: i64, direction: Direction) -> Self {
+        let decimal = match &self.number {
+            Backend::Decimal(d) => *d,
+            _ if places >= 0 => return self.clone(),
+            _ => {
+                let unit = BigInt::from(10).pow(-places as u32);
+                let (floor, remainder): (BigInt, BigInt) = self.integer().unwrap_or_default().div_mod_floor(&unit);
+                let half = (&remainder * BigInt::from(2)).cmp(&unit);
+                return Self::from(direction.settle(floor, remainder.is_zero(), half) * unit);
+            }
+        };
+        if !decimal.is_finite() {
+            return self.clone();
+        }
+
+        // move the place to round at to the units. quantizing it to
+        // a whole number fails if it has too many digits to have a
+        // fraction, in which case it is already rounded
+        let shifted = decimal.scaleb(BackendNumber::from(places));
+        let nearest = shifted.quantize(BackendNumber::zero());
+        let settled = match decimal_to_bigint(nearest) {
+            Some(nearest) => {
+                let mut floor = nearest;
+                let mut fraction = shifted - Self::from(floor.clone()).to_decimal();
+                if fraction < BackendNumber::zero() {
+                    floor -= 1;
+                    fraction += BackendNumber::from(1);
+                }
+                let half = fraction.partial_cmp(&BackendNumber::from_str("0.5").unwrap_or_default());
+                direction.settle(floor, fraction.is_zero(), half.unwrap_or(Ordering::Equal))
+            },
+            None if places > 0 => return self.clone(),
+            None => return Self::from(decimal_to_bigint(decimal).unwrap_or_default())
+        };
+
+        if places > 0 {
+            let settled = Self::from(settled).to_decimal();
+            return Self::from_decimal(settled.scaleb(BackendNumber::from(-places)));
+        }
+        Self::from(settled * BigInt::from(10).pow(-places as u32))
+    }
+
+    pub fn floor(&self, places: i64) -> Self {
+        self.round_to(places, Direction::Floor)
+    }
+
+    pub fn ceil(&self, places: i64) -> Self {
+        self.round_to(places, Direction::Ceil)
+    }
+
+    pub fn round(&self, places: i64) -> Self {
+        self.round_to(places, Direction::Round)
+    }
+
+    pub fn trunc(&self, places: i64) -> Self {
+        self.round_to(places, Direction::Trunc)
+    }
+
+    // the smaller of two numbers, or NaN if either is NaN
+    pub fn min(&self, other: &Self) -> Self {
+        match self.numeric_cmp(other) {
+            Some(Ordering::Greater) => other.clone(),
+            Some(_) => self.clone(),
+            None => Self::from_decimal(nan())
+        }
+    }
+
+    // the larger of two numbers, or NaN if either is NaN
+    pub fn max(&self, other: &Self) -> Self {
+        match self.numeric_cmp(other) {
+            Some(Ordering::Less) => other.clone(),
+            Some(_) => self.clone(),
+            None => Self::from_decimal(nan())
+        }
+    }
+
+    // exact for perfect squares, and NaN for negative numbers
+    pub fn sqrt(&self) -> Self {
+        if let Some(n) = self.integer() {
+            if !n.is_negative() {
+                let root = n.sqrt();
+                if &root * &root == n {
+                    return Self::from(root);
+                }
+            }
+        }
+        let half = BackendNumber::from_str("0.5").unwrap_or_default();
+        Self::from_decimal(self.to_decimal().pow(half))
+    }
+
+    pub fn exp(&self) -> Self {
+        // d128's own exp passes decNumber the wrong arguments,
+        // so this raises e to the power instead
+        let e = BackendNumber::from_str(E).unwrap_or_default();
+        Self::from_decimal(e.pow(self.to_decimal()))
+    }
+
+    // the natural logarithm, which is -Infinity for 0 and NaN for negatives
+    pub fn ln(&self) -> Self {
+        Self::from_decimal(self.to_decimal().ln())
+    }
+
+    pub fn log10(&self) -> Self {
+        Self::from_decimal(self.to_decimal().log10())
+    }
+
+    // the trig functions work in radians through f64,
+    // so they are accurate to about 16 digits
+    pub fn sin(&self) -> Self {
+        Self::from_f64(self.to_f64().sin())
+    }
+
+    pub fn cos(&self) -> Self {
+        Self::from_f64(self.to_f64().cos())
+    }
+
+    pub fn tan(&self) -> Self {
+        Self::from_f64(self.to_f64().tan())
+    }
+
+    pub fn asin(&self) -> Self {
+        Self::from_f64(self.to_f64().asin())
+    }
+
+    pub fn acos(&self) -> Self {
+        Self::from_f64(self.to_f64().acos())
+    }
+
+    pub fn atan(&self) -> Self {
+        Self::from_f64(self.to_f64().atan())
+    }
+
+    // the bitwise operators work on integers as two's complement
+    // with infinitely many sign bits, and give None for decimals
+    pub fn bitand(&self, rhs: &Self) -> Option<Self> {
+        match (&self.number, &rhs.number) {
+            (Backend::Small(a), Backend::Small(b)) => Some(Self::from(a & b)),
+            _ => Some(Self::from(self.integer()? & rhs.integer()?))
+        }
+    }
+
+    pub fn bitor(&self, rhs: &Self) -> Option<Self> {
+        match (&self.number, &rhs.number) {
+            (Backend::Small(a), Backend::Small(b)) => Some(Self::from(a | b)),
+            _ => Some(Self::from(self.integer()? | rhs.integer()?))
+        }
+    }
+
+    pub fn bitxor(&self, rhs: &Self) -> Option<Self> {
+        match (&self.number, &rhs.number) {
+            (Backend::Small(a), Backend::Small(b)) => Some(Self::from(a ^ b)),
+            _ => Some(Self::from(self.integer()? ^ rhs.integer()?))
+        }
+    }
+
+    pub fn shl(&self, amount: usize) -> Option<Self> {
+        Some(Self::from(self.integer()? << amount))
+    }
+
+    // shifting right rounds down, so -1 stays -1
+    pub fn shr(&self, amount: usize) -> Option<Self> {
+        Some(Self::from(self.integer()? >> amount))
     }
 
     pub fn unwrap(&self) -> Self {
@@ -113,38 +655,95 @@ impl Number {
 }
 
 
+impl FromStr for Number {
+    type Err = RuntimeError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if is_integer_literal(s) {
+            if let Ok(i) = s.parse::<i64>() {
+                return Ok(Self::from(i));
+            }
+            if let Ok(n) = s.parse::<BigInt>() {
+                return Ok(Self::from(n));
+            }
+        }
+
+        let number = string_to_backend_number(s)?;
+        Ok(Self::from_decimal(number))
+    }
+}
+
+impl From<BigInt> for Number {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(i) => Self::from(i),
+            None => Self {number: Backend::Big(n)}
+        }
+    }
+}
+
+impl From<i32> for Number {
+    fn from(n: i32) -> Self {
+        Self::from(n as i64)
+    }
+}
+
+impl From<i64> for Number {
+    fn from(n: i64) -> Self {
+        Self {number: Backend::Small(n)}
+    }
+}
+
+impl From<u64> for Number {
+    fn from(n: u64) -> Self {
+        Self::from(BigInt::from(n))
+    }
+}
+
+impl From<i128> for Number {
+    fn from(n: i128) -> Self {
+        Self::from(BigInt::from(n))
+    }
+}
+
+impl From<u128> for Number {
+    fn from(n: u128) -> Self {
+        Self::from(BigInt::from(n))
+    }
+}
+
+
 impl Add for Number {
     type Output = Number;
     fn add(self, rhs: Self) -> Self::Output {
-        Self::from_big_rational(
-            self.number + rhs.number
-        )
+        operate(self, rhs, i64::checked_add, |a, b| Some(a + b), |a, b| a + b)
     }
 }
 
 impl Mul for Number {
     type Output = Number;
     fn mul(self, rhs: Self) -> Self::Output {
-        Self::from_big_rational(
-            self.number * rhs.number
-        )
+        operate(self, rhs, i64::checked_mul, |a, b| Some(a * b), |a, b| a * b)
     }
 }
 
 impl Sub for Number {
     type Output = Number;
     fn sub(self, rhs: Self) -> Self::Output {
-        Self::from_big_rational(
-            self.number - rhs.number
-        )
+        operate(self, rhs, i64::checked_sub, |a, b| Some(a - b), |a, b| a - b)
     }
 }
 
+// integers that divide exactly stay integers, and
+// any other quotient is a decimal
 impl Div for Number {
     type Output = Number;
     fn div(self, rhs: Self) -> Self::Output {
-        Self::from_big_rational(
-            self.number / rhs.number
+        operate(
+            self,
+            rhs,
+            |a, b| if a.checked_rem(b) == Some(0) { a.checked_div(b) } else { None },
+            |a, b| if !b.is_zero() && (a % b).is_zero() { Some(a / b) } else { None },
+            |a, b| a / b
         )
     }
 }
@@ -152,16 +751,126 @@ impl Div for Number {
 impl Rem for Number {
     type Output = Number;
     fn rem(self, rhs: Self) -> Self::Output {
-        Self::from_big_rational(
-            self.number % rhs.number
+        operate(
+            self,
+            rhs,
+            i64::checked_rem,
+            |a, b| if b.is_zero() { None } else { Some(a % b) },
+            |a, b| a % b
         )
     }
 }
 
 
+impl Neg for Number {
+    type Output = Number;
+    fn neg(self) -> Self::Output {
+        match self.number {
+            Backend::Small(i) => match i.checked_neg() {
+                Some(n) => Self::from(n),
+                None => Self::from(-BigInt::from(i))
+            },
+            Backend::Big(n) => Self::from(-n),
+            Backend::Decimal(d) => Self::from_decimal(-d)
+        }
+    }
+}
+
+
 impl Display for Number {
     fn fmt(&self, f: &mut Formatter) -> Result {
         // write!(f, "{}", self.number)
-        write!(f, "{}", self.number)
+        match &self.number {
+            Backend::Small(i) => write!(f, "{}", i),
+            Backend::Big(n) => write!(f, "{}", n),
+            Backend::Decimal(d) => write!(f, "{}", d)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(s: &str) -> Number {
+        s.parse().expect("test numbers parse")
+    }
+
+    #[test]
+    fn integers_stay_exact_past_i64() {
+        let big = n("9223372036854775807") + n("1");
+        assert!(big.is_integer());
+        assert_eq!(big.to_string(), "9223372036854775808");
+        assert_eq!((big - n("1")).to_i64(), Some(i64::MAX));
+
+        let product = n("123456789012345678901234567890") * n("987654321098765432109876543210");
+        assert_eq!(product.to_string(), "121932631137021795226185032733622923332237463801111263526900");
+    }
+
+    #[test]
+    fn division_is_exact_only_without_a_remainder() {
+        assert!((n("10") / n("2")).is_integer());
+        assert_eq!(n("10") / n("4"), n("2.5"));
+        assert!(!(n("10") / n("4")).is_integer());
+        assert_eq!(n("-7") % n("2"), n("-1"));
+    }
+
+    #[test]
+    fn decimals_make_the_result_a_decimal() {
+        let sum = n("1") + n("0.5");
+        assert!(!sum.is_integer());
+        assert_eq!(sum, n("1.5"));
+        assert_eq!(n("2.0"), n("2"));
+    }
+
+    #[test]
+    fn conversions_are_checked() {
+        assert_eq!(n("340282366920938463463374607431768211455").to_u128(), Some(u128::MAX));
+        assert_eq!(n("340282366920938463463374607431768211456").to_u128(), None);
+        assert_eq!(n("-1").to_u64(), None);
+        assert_eq!(n("2.5").to_i64(), None);
+        assert_eq!(n("2.0").to_i64(), Some(2));
+        assert_eq!(n("1E+3").to_i32(), Some(1000));
+        assert_eq!(n("NaN").to_i64(), None);
+    }
+
+    #[test]
+    fn negating_the_smallest_i64_does_not_overflow() {
+        let smallest = Number::from(i64::MIN);
+        assert_eq!((-smallest).to_string(), "9223372036854775808");
+    }
+
+    #[test]
+    fn nan_is_ordered_last_and_equal_to_itself() {
+        let nan = n("NaN");
+        assert_eq!(nan.cmp(&n("Infinity")), Ordering::Greater);
+        assert_eq!(nan.partial_cmp(&nan), Some(Ordering::Equal));
+        assert_eq!(nan, n("-NaN"));
+        assert!(n("1") < nan);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn numeric_comparison_leaves_nan_unordered() {
+        assert_eq!(n("NaN").numeric_cmp(&n("NaN")), None);
+        assert_eq!(n("1").numeric_cmp(&n("NaN")), None);
+        assert_eq!(n("2").numeric_cmp(&n("1.5")), Some(Ordering::Greater));
+        assert!(Number::min(&n("NaN"), &n("1")).is_nan());
+        assert_eq!(Number::max(&n("-Infinity"), &n("1")), n("1"));
+    }
+
+    #[test]
+    fn big_integers_compare_exactly_with_decimals() {
+        let above = n("10000000000000000000000000000000000000001");
+        assert_eq!(above.numeric_cmp(&n("1E+40")), Some(Ordering::Greater));
+        assert_eq!(n("1E+40").numeric_cmp(&above), Some(Ordering::Less));
+        assert_eq!((-above.clone()).numeric_cmp(&n("-1E+40")), Some(Ordering::Less));
+        assert_ne!(above, n("1E+40"));
+
+        let big = n("99999999999999999999");
+        assert_eq!(big.numeric_cmp(&n("99999999999999999999.5")), Some(Ordering::Less));
+        assert_eq!(big.numeric_cmp(&n("99999999999999999998.5")), Some(Ordering::Greater));
+        assert_eq!(big.numeric_cmp(&n("-Infinity")), Some(Ordering::Greater));
+        assert_eq!(big.numeric_cmp(&n("NaN")), None);
+    }
+}
@@ -1,38 +1,32 @@
 
-use std::fmt::{Debug, Display};
+use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
 use std::ops::{Add, Sub, Mul, Div, Rem, Not};
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use crate::number::Number;
 use crate::error::*;
 use crate::table::Table;
+use crate::map::Map;
 
 pub type Contents = Vec<Number>;
 pub const NOTHING : &[Number] = &[];
 
 
 fn float64_to_number(num: f64) -> Number {
-    Number::from_str(&num.to_string())
-}
-
-
-fn from_string(string: String) -> Contents {
-    let mut result = vec![];
-    for ch in string.chars() {
-        result.push(
-            Number::from_str(&(ch as i32).to_string())
-        );
-    }
-    return result;
+    num.to_string()
+        .parse()
+        .expect("an f64 always formats as a valid decimal number")
 }
 
 
 fn from_number(number: Number) -> Contents {
-    return vec![number];
+    vec![number]
 }
 
 
-pub fn string_to_number(n: String) -> Number {
+pub fn string_to_number(n: String) -> Result<Number, RuntimeError> {
     // println!("String to num");
     // match Number::from_str(&n) {
     //     Ok(s) => s,
@@ -42,7 +36,7 @@ pub fn string_to_number(n: String) -> Number {
     //     Ok(n) => n,
     //     Err(_) => 0 as f64
     // }
-    Number::from_str(&n)
+    n.parse()
 }
 
 
@@ -71,65 +65,433 @@ pub enum Instruction {
     GetAttr,
     SetAttr,
     Execute,
-    Pass
+    Pass,
+    IsProblem,
+    ProblemKind,
+    ProblemMessage,
+    ProblemPayload,
+    Raise,
+    Try,
+    Slice,
+    NewMap,
+    MapInsert,
+    MapGet,
+    MapRemove,
+    MapContains,
+    MapKeys,
+    MapValues,
+    MapLength,
+    And,
+    Or,
+    Xor,
+    AndThen,
+    OrElse,
+    Pow,
+    IntDiv,
+    Abs,
+    Neg,
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+    Min,
+    Max,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    IsNan,
+    IsInfinite,
+    FormatNum,
+    ToRadix,
+    ParseNum
+}
+
+// parse an instruction from its mnemonic, ignoring case
+impl FromStr for Instruction {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "print" => Instruction::Print,
+            "println" => Instruction::Println,
+            "while" => Instruction::While,
+            "if" => Instruction::If,
+            "listfromstack" => Instruction::ListFromStack,
+            "append" => Instruction::Append,
+            "pop" => Instruction::Pop,
+            "index" => Instruction::Index,
+            "equal" => Instruction::Equal,
+            "greater" => Instruction::Greater,
+            "less" => Instruction::Less,
+            "not" => Instruction::Not,
+            "add" => Instruction::Add,
+            "mul" => Instruction::Mul,
+            "sub" => Instruction::Sub,
+            "div" => Instruction::Div,
+            "mod" => Instruction::Mod,
+            "call" => Instruction::Call,
+            "load" => Instruction::Load,
+            "store" => Instruction::Store,
+            "getattr" => Instruction::GetAttr,
+            "setattr" => Instruction::SetAttr,
+            "execute" => Instruction::Execute,
+            "pass" => Instruction::Pass,
+            "isproblem" => Instruction::IsProblem,
+            "problemkind" => Instruction::ProblemKind,
+            "problemmessage" => Instruction::ProblemMessage,
+            "problempayload" => Instruction::ProblemPayload,
+            "raise" => Instruction::Raise,
+            "try" => Instruction::Try,
+            "slice" => Instruction::Slice,
+            "newmap" => Instruction::NewMap,
+            "mapinsert" => Instruction::MapInsert,
+            "mapget" => Instruction::MapGet,
+            "mapremove" => Instruction::MapRemove,
+            "mapcontains" => Instruction::MapContains,
+            "mapkeys" => Instruction::MapKeys,
+            "mapvalues" => Instruction::MapValues,
+            "maplength" => Instruction::MapLength,
+            "and" => Instruction::And,
+            "or" => Instruction::Or,
+            "xor" => Instruction::Xor,
+            "andthen" => Instruction::AndThen,
+            "orelse" => Instruction::OrElse,
+            "pow" => Instruction::Pow,
+            "intdiv" => Instruction::IntDiv,
+            "abs" => Instruction::Abs,
+            "neg" => Instruction::Neg,
+            "floor" => Instruction::Floor,
+            "ceil" => Instruction::Ceil,
+            "round" => Instruction::Round,
+            "trunc" => Instruction::Trunc,
+            "min" => Instruction::Min,
+            "max" => Instruction::Max,
+            "sqrt" => Instruction::Sqrt,
+            "exp" => Instruction::Exp,
+            "ln" => Instruction::Ln,
+            "log10" => Instruction::Log10,
+            "sin" => Instruction::Sin,
+            "cos" => Instruction::Cos,
+            "tan" => Instruction::Tan,
+            "asin" => Instruction::Asin,
+            "acos" => Instruction::Acos,
+            "atan" => Instruction::Atan,
+            "bitand" => Instruction::BitAnd,
+            "bitor" => Instruction::BitOr,
+            "bitxor" => Instruction::BitXor,
+            "shl" => Instruction::Shl,
+            "shr" => Instruction::Shr,
+            "isnan" => Instruction::IsNan,
+            "isinfinite" => Instruction::IsInfinite,
+            "formatnum" => Instruction::FormatNum,
+            "toradix" => Instruction::ToRadix,
+            "parsenum" => Instruction::ParseNum,
+            _ => return Err(())
+        })
+    }
+}
+
+// an index or slice bound as an integer, or the problem with it
+fn integer_index<T: Object>(index: &T) -> Result<i64, T> {
+    if index.is_problem() {
+        return Err(index.clone());
+    }
+    if index.get_type() != Type::Num {
+        return Err(T::from_problem_with(
+            Problem::TypeError,
+            &format!("expected a number as an index, found a {:?} value", index.get_type()),
+            Some(index.clone())
+        ));
+    }
+    match index.as_number().to_i64() {
+        Some(i) => Ok(i),
+        None => Err(T::from_problem_with(
+            Problem::ValueError,
+            &format!("index {} is not an integer", index.as_number()),
+            Some(index.clone())
+        ))
+    }
+}
+
+// the position of an index in a sequence of a given length,
+// counting negative indices back from the end
+fn resolve_index<T: Object>(index: &T, length: usize) -> Result<usize, T> {
+    let i = integer_index(index)?;
+    let position = if i < 0 { i + length as i64 } else { i };
+    if position < 0 || position >= length as i64 {
+        return Err(T::from_problem_with(
+            Problem::OutOfRange,
+            &format!("index {} is out of range for length {}", i, length),
+            Some(index.clone())
+        ));
+    }
+    Ok(position as usize)
+}
+
+// the name of the attribute an instance is indexed with
+fn instance_key<T: Object>(index: &T) -> Result<&str, T> {
+    match index.get_type() {
+        Type::Str => Ok(index.get_string()),
+        Type::Problem(_) => Err(index.clone()),
+        t => Err(T::from_problem_with(
+            Problem::TypeError,
+            &format!("expected a string as an instance key, found a {:?} value", t),
+            Some(index.clone())
+        ))
+    }
+}
+
+// the positions a slice takes from a sequence of a given length
+fn slice_positions<T: Object>(length: usize, start: &T, end: &T, step: &T) -> Result<Vec<usize>, T> {
+    let bound = |b: &T| match b.get_type() {
+        Type::Nothing => Ok(None),
+        _ => integer_index(b).map(Some)
+    };
+
+    let step = bound(step)?.unwrap_or(1);
+    if step == 0 {
+        return Err(T::from_problem_with(Problem::ValueError, "slice step cannot be zero", None));
+    }
+
+    // negative bounds count from the end, and then every bound is
+    // clamped to the positions a slice in that direction can reach
+    let length = length as i64;
+    let clamp = |b: i64| {
+        let b = if b < 0 { b + length } else { b };
+        if step > 0 {
+            b.max(0).min(length)
+        } else {
+            b.max(-1).min(length - 1)
+        }
+    };
+    let (default_start, default_end) = if step > 0 { (0, length) } else { (length - 1, -1) };
+    let start = bound(start)?.map_or(default_start, clamp);
+    let end = bound(end)?.map_or(default_end, clamp);
+
+    let mut positions = vec![];
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        positions.push(i as usize);
+        i = match i.checked_add(step) {
+            Some(next) => next,
+            None => break
+        };
+    }
+    Ok(positions)
 }
 
+
+// the kind of a problem value. a problem also carries a message,
+// kept in its string, and an optional payload, kept in its list
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Problem {
-    IncompatibleTypes,
-    ValueError,
-    OutOfRange,
+    IncompatibleTypes,  // an operator was given values of two different types
+    ValueError,         // a value could not be used, such as a badly formed number
+    OutOfRange,         // an index was past the end of a list or string
+    DivisionByZero,     // a number was divided by zero
+    KeyError,           // a key or attribute was missing
+    TypeError,          // an operator was given a type it does not support
+    ArityError,         // a function was given the wrong number of arguments
+    Overflow,           // a number was too large to be represented
+    RuntimeError,       // `try` caught a runtime error, named by the payload
+}
+
+impl Problem {
+    // the message of a problem that wasn't given one
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            Problem::IncompatibleTypes => "the values have incompatible types",
+            Problem::ValueError => "invalid value",
+            Problem::OutOfRange => "index out of range",
+            Problem::DivisionByZero => "division by zero",
+            Problem::KeyError => "missing key",
+            Problem::TypeError => "unsupported type",
+            Problem::ArityError => "wrong number of arguments",
+            Problem::Overflow => "number too large",
+            Problem::RuntimeError => "runtime error",
+        }
+    }
+}
+
+// a rust function or closure that programs can call with `Execute`
+// or `Call`. two foreign functions are only equal if they are the same closure
+pub struct ForeignFunction<T> {
+    function: Rc<dyn Fn(T) -> Result<T, RuntimeError>>,
+    arity: Option<usize>   // how many arguments `Call` gives a native function
+}
+
+impl<T> ForeignFunction<T> {
+    pub fn new<F: Fn(T) -> Result<T, RuntimeError> + 'static>(function: F) -> Self {
+        Self {function: Rc::new(function), arity: None}
+    }
+
+    // a closure that changes its captured state. it fails with a
+    // ForeignFunction error if it ends up calling itself
+    pub fn new_mut<F: FnMut(T) -> Result<T, RuntimeError> + 'static>(function: F) -> Self {
+        let function = RefCell::new(function);
+        Self::new(move |object| match function.try_borrow_mut() {
+            Ok(mut f) => f(object),
+            Err(_) => Err(RuntimeError::new(
+                ErrorKind::ForeignFunction,
+                "Foreign function was called while it was already running"
+            ))
+        })
+    }
+
+    pub fn call(&self, object: T) -> Result<T, RuntimeError> {
+        (self.function)(object)
+    }
+
+    // the number of arguments of a native function, or None if
+    // the function takes the single object it is called with
+    pub fn arity(&self) -> Option<usize> {
+        self.arity
+    }
+}
+
+impl<T: Object> ForeignFunction<T> {
+    // a function that `Call` gives a fixed number of arguments, in the
+    // order they were pushed. it is called with a list of the arguments
+    pub fn native<F: Fn(Vec<T>) -> Result<T, RuntimeError> + 'static>(arity: usize, function: F) -> Self {
+        Self {
+            function: Rc::new(move |arguments: T| {
+                let arguments = arguments.as_list();
+                if arguments.len() != arity {
+                    return Ok(arity_problem(arity, arguments.len()));
+                }
+                function(arguments)
+            }),
+            arity: Some(arity)
+        }
+    }
+}
+
+// the problem a function gives when called with the wrong number of arguments
+pub fn arity_problem<T: Object>(expected: usize, found: usize) -> T {
+    T::from_problem_with(
+        Problem::ArityError,
+        &format!("expected {} arguments, found {}", expected, found),
+        None
+    )
+}
+
+impl<T: 'static> From<fn(T) -> T> for ForeignFunction<T> {
+    fn from(function: fn(T) -> T) -> Self {
+        Self::new(move |object| Ok(function(object)))
+    }
+}
+
+impl<T> From<Rc<dyn Fn(T) -> Result<T, RuntimeError>>> for ForeignFunction<T> {
+    fn from(function: Rc<dyn Fn(T) -> Result<T, RuntimeError>>) -> Self {
+        Self {function, arity: None}
+    }
+}
+
+impl<T> Clone for ForeignFunction<T> {
+    fn clone(&self) -> Self {
+        Self {function: self.function.clone(), arity: self.arity}
+    }
+}
+
+impl<T> PartialEq for ForeignFunction<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl<T> Debug for ForeignFunction<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.arity {
+            Some(n) => write!(f, "ForeignFunction({:p}, arity {})", Rc::as_ptr(&self.function) as *const (), n),
+            None => write!(f, "ForeignFunction({:p})", Rc::as_ptr(&self.function) as *const ())
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Type {
     Str,
     Num,
+    Bool,
     List,
     Function,
     Instance,
+    Map,
     Problem(Problem),
     Nothing,
     Command(Instruction)
 }
 
 
-pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem + Ord + Not {
+pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem + Ord + Not + 'static {
     // initializers
     fn new(value_type: Type, contents: Contents) -> Self;
 
     fn empty_instance() -> Self {
-        return Self::new(Type::Instance, NOTHING.to_vec());
+        Self::new(Type::Instance, NOTHING.to_vec())
     }
 
     fn from_string(string: String) -> Self {
-        return Self::new(Type::Str, from_string(string));
+        Self::from_str(&string)
     }
 
     fn from_str(string: &str) -> Self {
-        return Self::new(Type::Str, from_string(string.to_string()));
+        let mut instance = Self::new(Type::Str, NOTHING.to_vec());
+        instance.set_string(string);
+        instance
     }
 
     fn from_f64(decimal: f64) -> Self {
-        return Self::new(Type::Num, from_number(float64_to_number(decimal)));
+        Self::new(Type::Num, from_number(float64_to_number(decimal)))
     }
 
     fn from_number(n: Number) -> Self {
-        return Self::new(Type::Num, from_number(n));
+        Self::new(Type::Num, from_number(n))
+    }
+
+    // a boolean holds 1 or 0 as its number
+    fn from_bool(b: bool) -> Self {
+        Self::new(Type::Bool, from_number(Number::from(b as i32)))
     }
 
     fn from_instruction(instruction: Instruction) -> Self {
-        return Self::new(Type::Command(instruction), NOTHING.to_vec());
+        Self::new(Type::Command(instruction), NOTHING.to_vec())
     }
 
     fn from_problem(problem: Problem) -> Self {
-        return Self::new(Type::Problem(problem), NOTHING.to_vec());
+        Self::from_problem_with(problem, problem.default_message(), None)
+    }
+
+    fn from_problem_with(problem: Problem, message: &str, payload: Option<Self>) -> Self {
+        let mut instance = Self::new(Type::Problem(problem), NOTHING.to_vec());
+        instance.set_string(message);
+        if let Some(p) = payload {
+            instance.set_list(vec![p]);
+        }
+        instance
     }
 
     fn from_vector(vector: Vec<Self>) -> Self {
         let mut instance = Self::new(Type::List, NOTHING.to_vec());
         instance.set_list(vector);
-        return instance;
+        instance
+    }
+
+    fn from_map(map: Map<Self>) -> Self {
+        let mut instance = Self::new(Type::Map, NOTHING.to_vec());
+        instance.set_map(map);
+        instance
     }
 
     fn from_nothing() -> Self {
@@ -142,64 +504,98 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
     fn from_function(vector: Vec<Self>) -> Self {
         let mut instance = Self::new(Type::Function, NOTHING.to_vec());
         instance.set_list(vector);
-        return instance;
+        instance
     }
 
     fn from_foreign_function(function: fn(Self) -> Self) -> Self {
+        Self::from_foreign(function.into())
+    }
+
+    fn from_closure<F: Fn(Self) -> Result<Self, RuntimeError> + 'static>(closure: F) -> Self {
+        Self::from_foreign(ForeignFunction::new(closure))
+    }
+
+    fn from_closure_mut<F: FnMut(Self) -> Result<Self, RuntimeError> + 'static>(closure: F) -> Self {
+        Self::from_foreign(ForeignFunction::new_mut(closure))
+    }
+
+    fn from_foreign(function: ForeignFunction<Self>) -> Self {
         let mut instance = Self::new(
             Type::Function,
             NOTHING.to_vec()
             );
         instance.set_foreign_function(function);
-        return instance;
+        instance
     }
 
     // helper functions
     fn get_type(&self) -> Type;
-    fn get_list(&self) -> Vec<Self>;
-    fn get_contents(&self) -> Contents;
-    fn get_attributes(&self) -> Table<Self>;
-    fn get_foreign_function(&self) -> fn(Self) -> Self;
+    fn get_list(&self) -> &[Self];
+    fn get_contents(&self) -> &[Number];
+    fn get_string(&self) -> &str;
+    fn get_attributes(&self) -> &Table<Self>;
+    fn get_map(&self) -> &Map<Self>;
+    fn get_foreign_function(&self) -> ForeignFunction<Self>;
+    fn is_foreign_function(&self) -> bool;
+
+    // mutable access to storage that may be shared with other objects,
+    // copying it first if it is, so the other objects don't change
+    fn get_list_mut(&mut self) -> &mut Vec<Self>;
+    fn get_contents_mut(&mut self) -> &mut Contents;
+    fn get_attributes_mut(&mut self) -> &mut Table<Self>;
+    fn get_map_mut(&mut self) -> &mut Map<Self>;
+
+    // an approximation of how much memory this object uses, counting
+    // one cell for itself and for each number or character it holds,
+    // plus the sizes of its list items and attributes
+    fn size(&self) -> usize;
 
     fn set_type(&mut self, object_type: Type);
     fn set_list(&mut self, list: Vec<Self>);
     fn set_contents(&mut self, contents: Contents);
+    fn set_string(&mut self, string: &str);
     fn set_attributes(&mut self, attributes: Table<Self>);
-    fn set_foreign_function(&mut self, function: fn(Self) -> Self);
+    fn set_map(&mut self, map: Map<Self>);
+    fn set_foreign_function(&mut self, function: ForeignFunction<Self>);
 
     // getters
     fn get_attr(&self, name: String) -> Self {
-        let table = self.get_attributes();
-        let raw_attr = table.get(name);
-        let attr = match raw_attr {
-            Some(s) => s,
+        let raw_attr = self.get_attributes().get_ref(&name);
+        
+        match raw_attr {
+            Some(s) => s.clone(),
             None => Self::new(Type::Nothing, NOTHING.to_vec())
-        };
-        return attr;
+        }
     }
 
     fn as_number(&self) -> Number {
-        if self.get_contents().len() > 0 {
+        if !self.get_contents().is_empty() {
             self.get_contents()[0].clone()
         } else {
             // Number::from_str("0").unwrap()
-            string_to_number("0".to_string())
+            Number::from(0)
         }
     }
 
     fn as_usize(&self) -> usize {
-        if self.get_contents().len() > 0 {
+        if !self.get_contents().is_empty() {
             // match self.get_contents()[0].to_i32() {
             //     Some(i) => i as usize,
             //     None => 0 as usize
             // }
-            self.get_contents()[0].to_usize()
+            // numbers that aren't a whole usize, like -1 or 2.5, are 0
+            self.get_contents()[0].to_usize().unwrap_or(0)
         } else {
-            0 as usize
+            0
         }
     }
 
     fn as_string(&self) -> String {
+        if self.get_type() == Type::Str {
+            return self.get_string().to_string();
+        }
+
+        // other objects are read as a string of character codes
         let mut result = "".to_string();
 
         for ch in self.get_contents() {
@@ -210,11 +606,11 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
             let character = ch.to_char();
             result += &character.to_string();
         }
-        return result.to_string();
+        result.to_string()
     }
     
     fn as_list(&self) -> Vec<Self> {
-        self.get_list()
+        self.get_list().to_vec()
     }
 
     fn as_instruction(&self) -> Instruction {
@@ -225,124 +621,201 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
     }
     
     fn as_instance(&self) -> Table<Self> {
-        return self.get_attributes();
+        self.get_attributes().clone()
     }
     
     
-    fn as_foreign_function(&self) -> fn(Self) -> Self {
-        return self.get_foreign_function();
+    fn as_foreign_function(&self) -> ForeignFunction<Self> {
+        self.get_foreign_function()
+    }
+
+    fn as_bool(&self) -> bool {
+        self.as_number() != Number::from(0)
+    }
+
+    // whether `If`, `While` and the logical instructions treat this
+    // object as true. false, zero, none, problems and empty strings,
+    // lists, instances and maps are false, and everything else is true
+    fn is_truthy(&self) -> bool {
+        match self.get_type() {
+            Type::Bool | Type::Num => self.as_bool(),
+            Type::Str => !self.get_string().is_empty(),
+            Type::List => !self.get_list().is_empty(),
+            Type::Instance => !self.get_attributes().is_empty(),
+            Type::Map => !self.get_map().is_empty(),
+            Type::Nothing | Type::Problem(_) => false,
+            Type::Function | Type::Command(_) => true
+        }
+    }
+
+    fn is_problem(&self) -> bool {
+        matches!(self.get_type(), Type::Problem(_))
+    }
+
+    // the message of a problem, or None if this isn't a problem
+    fn problem_message(&self) -> Option<&str> {
+        if self.is_problem() {
+            Some(self.get_string())
+        } else {
+            None
+        }
+    }
+
+    // the value a problem was raised with, if it has one
+    fn problem_payload(&self) -> Option<Self> {
+        if self.is_problem() {
+            self.get_list().first().cloned()
+        } else {
+            None
+        }
     }
     
     // setters
     fn set_attr(&mut self, name: String, object: Self) {
-        let mut table = self.get_attributes();
-        table.set(name, object);
-        self.set_attributes(table);
+        self.get_attributes_mut().set(name, object);
     }
 
-    fn get_attr_recursive(&mut self, names: Vec<String>) -> Self {
-        if names.len() < 1 {
-            throw_no_stack("Could not set attribute of object without the attribute name");
+    fn get_attr_recursive(&mut self, names: Vec<String>) -> Result<Self, RuntimeError> {
+        if names.is_empty() {
+            return Err(RuntimeError::new(
+                ErrorKind::MissingAttribute,
+                "Could not get attribute of object without the attribute name"
+            ));
         }
 
         let name = &names[0];
         let table = self.get_attributes();
         if names.len() == 1 {
 
-            match table.get(name.to_string()) {
-                Some(o) => o,
+            Ok(match table.get_ref(name) {
+                Some(o) => o.clone(),
                 None => Self::from_nothing()
-            }
+            })
 
         } else {
 
-            match table.get(name.to_string()) {
-                Some(o) => o,
+            match table.get_ref(name) {
+                Some(o) => o.clone(),
                 None => Self::empty_instance()
             }.get_attr_recursive(names[1..].to_vec())
         }
     }
 
-    fn set_attr_recursive(&mut self, names: Vec<String>, object: Self) -> Self {
-        if names.len() < 1 {
-            throw_no_stack("Could not set attribute of object without the attribute name");
+    fn set_attr_recursive(&mut self, names: Vec<String>, object: Self) -> Result<Self, RuntimeError> {
+        if names.is_empty() {
+            return Err(RuntimeError::new(
+                ErrorKind::MissingAttribute,
+                "Could not set attribute of object without the attribute name"
+            ));
         }
 
         let name = &names[0];
-        let mut table = self.get_attributes();
+        let table = self.get_attributes_mut();
 
         if names.len() == 1 {
 
             table.set(name.to_string(), object);
-            self.set_attributes(table);
 
         } else {
 
-            table.set(
-                name.to_string(),
-                match table.get(name.to_string()) {
-                    Some(o) => o,
-                    None => Self::empty_instance()
-                }
-                    .set_attr_recursive(names[1..].to_vec(), object));
-            self.set_attributes(table);
+            // change the inner instance where it is, so that
+            // it isn't shared with a copy while it is changed
+            if table.get_ref(name).is_none() {
+                table.set(name.to_string(), Self::empty_instance());
+            }
+            match table.get_mut(name) {
+                Some(o) => o.set_attr_recursive(names[1..].to_vec(), object)?,
+                None => unreachable!("the attribute was just set")
+            };
 
         }
-        self.clone()
+        Ok(self.clone())
     }
 
 
+    // the item of a list, function or string at an index, counting
+    // negative indices back from the end, or the attribute of an
+    // instance with a string key. a missing item is a problem
     fn index(&mut self, index: Self) -> Self {
-        let my_type = self.get_type();
-        match my_type {
-            Type::Str => match self.as_string().chars().nth(index.as_usize()) {
-                Some(c) => {
-                    let mut string = String::new();
-                    string.push(c);
-                    Self::from_string(string)
-                },
-                None => {
-                    Self::from_problem(
-                        Problem::OutOfRange
-                    )
+        let result = match self.get_type() {
+            Type::Str => {
+                let characters: Vec<char> = self.get_string().chars().collect();
+                resolve_index(&index, characters.len())
+                    .map(|i| Self::from_string(characters[i].to_string()))
+            },
+            Type::List | Type::Function => resolve_index(&index, self.get_list().len())
+                .map(|i| self.get_list()[i].clone()),
+            Type::Instance => instance_key(&index).and_then(|key| {
+                match self.get_attributes().get_ref(key) {
+                    Some(attr) => Ok(attr.clone()),
+                    None => Err(Self::from_problem_with(
+                        Problem::KeyError,
+                        &format!("no attribute `{}`", key),
+                        Some(index.clone())
+                    ))
                 }
+            }),
+            Type::Problem(_) => Err(self.clone()),
+            t => Err(Self::from_problem_with(
+                Problem::TypeError,
+                &format!("cannot index a {:?} value", t),
+                None
+            ))
+        };
+        result.unwrap_or_else(|problem| problem)
+    }
+
+    // the items of a list or the characters of a string from start up to
+    // but not including end, taking every step-th one. the bounds may be
+    // negative to count from the end, or none to use the whole sequence,
+    // and a negative step goes backwards
+    fn slice(&self, start: Self, end: Self, step: Self) -> Self {
+        let result = match self.get_type() {
+            Type::Str => {
+                let characters: Vec<char> = self.get_string().chars().collect();
+                slice_positions(characters.len(), &start, &end, &step)
+                    .map(|positions| Self::from_string(positions.into_iter().map(|i| characters[i]).collect()))
             },
-            Type::List => self.as_list()[index.as_usize()].clone(),
-            Type::Function => self.as_list()[index.as_usize()].clone(),
-            _ => Self::from_problem(Problem::ValueError)
-        }
+            Type::List => slice_positions(self.get_list().len(), &start, &end, &step)
+                .map(|positions| Self::from_vector(positions.into_iter().map(|i| self.get_list()[i].clone()).collect())),
+            Type::Problem(_) => Err(self.clone()),
+            t => Err(Self::from_problem_with(
+                Problem::TypeError,
+                &format!("cannot slice a {:?} value", t),
+                None
+            ))
+        };
+        result.unwrap_or_else(|problem| problem)
     }
 
     fn list_push(&mut self, object: Self) {
         self.set_type(Type::List);
-
-        let mut list = self.get_list();
-        list.push(object);
-        self.set_list(list);
+        self.get_list_mut().push(object);
     }
 
     fn list_pop(&mut self) -> Self {
-        match self.get_list().pop() {
-            Some(e) => e,
+        match self.get_list().last() {
+            Some(e) => e.clone(),
             None => Self::new(Type::Nothing, NOTHING.to_vec())
         }
     }
 
-    fn call_foreign_function(&mut self, parameter: Self) -> Self {
-        self.get_foreign_function()(parameter)
+    fn call_foreign_function(&mut self, parameter: Self) -> Result<Self, RuntimeError> {
+        self.get_foreign_function().call(parameter)
     }
 
     fn format(&self) -> String {
         let object_type = self.get_type();
         match object_type {
-            Type::Str => format!("{}", self.as_string()),
+            Type::Str => self.get_string().to_string(),
             Type::Num => format!("{}", self.as_number()),
+            Type::Bool => format!("{}", self.as_bool()),
             Type::List => {
-                if self.as_list().len() == 0 {
+                if self.get_list().is_empty() {
                     return "[]".to_string();
                 }
                 let mut result = "[".to_string();
-                for item in self.as_list() {
+                for item in self.get_list() {
                     result += &item.format();
                     result += ", ";
                 }
@@ -352,7 +825,7 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
                 },
             Type::Instance => {
 
-                if self.get_attributes().keys().len() < 1 {
+                if self.get_attributes().is_empty() {
                     "<>".to_string()
                 } else {
                     let mut result = "<".to_string();
@@ -368,9 +841,15 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
                 }
 
             },
-            Type::Function => format!("Function"),
-            Type::Nothing => format!("None"),
-            Type::Problem(p) => format!("{:?}", p),
+            Type::Map => {
+                let entries: Vec<String> = self.get_map().iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_object::<Self>().format(), v.format()))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            },
+            Type::Function => "Function".to_string(),
+            Type::Nothing => "None".to_string(),
+            Type::Problem(p) => format!("{:?}: {}", p, self.get_string()),
             Type::Command(c) => format!("{:?}", c),
         }
     }
@@ -383,3 +862,158 @@ pub trait Object: Sized + Clone + Debug + Display + Add + Sub + Mul + Div + Rem
         println!("{}", self.format());
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+    use crate::literals::*;
+
+    #[test]
+    fn strings_keep_their_text() {
+        let text = "héllo wörld 👋";
+        let value = string(text);
+        assert_eq!(value.get_string(), text);
+        assert_eq!(value.as_string(), text);
+        assert_eq!(value.format(), text);
+        assert_eq!(value.size(), 1 + text.chars().count());
+    }
+
+    #[test]
+    fn strings_are_indexed_by_character() {
+        assert_eq!(string("héllo").index(num("1")), string("é"));
+        assert_eq!(string("a👋b").index(num("2")), string("b"));
+    }
+
+    fn numbers(values: &[&str]) -> Value {
+        list(&values.iter().map(|n| num(n)).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let mut items = numbers(&["1", "2", "3"]);
+        assert_eq!(items.index(num("0")), num("1"));
+        assert_eq!(items.index(num("-1")), num("3"));
+        assert_eq!(string("héllo").index(num("-4")), string("é"));
+    }
+
+    #[test]
+    fn bad_indices_are_problems() {
+        let mut items = numbers(&["1", "2", "3"]);
+        assert_eq!(items.index(num("3")).get_type(), Type::Problem(Problem::OutOfRange));
+        assert_eq!(items.index(num("-4")).get_type(), Type::Problem(Problem::OutOfRange));
+        assert_eq!(items.index(num("1.5")).get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(items.index(string("a")).get_type(), Type::Problem(Problem::TypeError));
+        assert_eq!(num("1").index(num("0")).get_type(), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn instances_are_indexed_by_attribute_name() {
+        let mut instance = Value::empty_instance();
+        instance.set_attr("x".to_string(), num("1"));
+        assert_eq!(instance.index(string("x")), num("1"));
+        assert_eq!(instance.index(string("y")).get_type(), Type::Problem(Problem::KeyError));
+        assert_eq!(instance.index(num("0")).get_type(), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn slices_take_a_range_with_a_step() {
+        let items = numbers(&["0", "1", "2", "3", "4"]);
+        assert_eq!(items.slice(num("1"), num("3"), none()), numbers(&["1", "2"]));
+        assert_eq!(items.slice(none(), none(), num("2")), numbers(&["0", "2", "4"]));
+        assert_eq!(items.slice(num("-2"), none(), none()), numbers(&["3", "4"]));
+        assert_eq!(items.slice(none(), none(), num("-1")), numbers(&["4", "3", "2", "1", "0"]));
+        assert_eq!(items.slice(num("-10"), num("10"), none()), items);
+        assert_eq!(items.slice(num("3"), num("1"), none()), numbers(&[]));
+        assert_eq!(string("héllo").slice(num("1"), num("-1"), none()), string("éll"));
+    }
+
+    #[test]
+    fn bad_slices_are_problems() {
+        let items = numbers(&["0", "1"]);
+        assert_eq!(items.slice(none(), none(), num("0")).get_type(), Type::Problem(Problem::ValueError));
+        assert_eq!(items.slice(string("a"), none(), none()).get_type(), Type::Problem(Problem::TypeError));
+        assert_eq!(num("1").slice(none(), none(), none()).get_type(), Type::Problem(Problem::TypeError));
+    }
+
+    #[test]
+    fn empty_and_zero_values_are_falsy() {
+        let falsy = [boolean(false), num("0"), none(), string(""), list(&[]),
+            Value::empty_instance(), Value::from_map(Map::new()), Value::from_problem(Problem::ValueError)];
+        for value in falsy.iter() {
+            assert!(!value.is_truthy(), "{:?} should be falsy", value);
+        }
+
+        let truthy = [boolean(true), num("-1"), num("0.5"), string("0"), list(&[none()]), fun(&[])];
+        for value in truthy.iter() {
+            assert!(value.is_truthy(), "{:?} should be truthy", value);
+        }
+    }
+
+    #[test]
+    fn not_gives_booleans_and_keeps_problems() {
+        assert_eq!(!string(""), boolean(true));
+        assert_eq!(!none(), boolean(true));
+        assert_eq!(!num("2"), boolean(false));
+        assert_eq!((!Value::from_problem(Problem::ValueError)).get_type(), Type::Problem(Problem::ValueError));
+    }
+
+    #[test]
+    fn strings_concatenate() {
+        assert_eq!(string("ü") + string("ber"), string("über"));
+    }
+
+    #[test]
+    fn character_codes_that_are_not_characters_are_replaced() {
+        let codes = Value::new(Type::Num, vec![0xD800.into(), 0x41.into()]);
+        assert_eq!(codes.as_string(), "\u{FFFD}A");
+    }
+
+    #[test]
+    fn closures_keep_their_state() {
+        let mut counter = 0;
+        let mut count = Value::from_closure_mut(move |_| {
+            counter += 1;
+            Ok(Value::from_number(counter.into()))
+        });
+        count.call_foreign_function(none()).unwrap();
+        assert_eq!(count.call_foreign_function(none()).unwrap(), num("2"));
+    }
+
+    #[test]
+    fn closures_that_call_themselves_fail() {
+        let this: Rc<RefCell<Option<Value>>> = Rc::new(RefCell::new(None));
+        let inner = this.clone();
+        let function = Value::from_closure_mut(move |x| {
+            let mut f = inner.borrow().clone().expect("the closure is set");
+            f.call_foreign_function(x)
+        });
+        *this.borrow_mut() = Some(function.clone());
+
+        let error = function.clone().call_foreign_function(none()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ForeignFunction);
+        this.borrow_mut().take();
+    }
+
+    #[test]
+    fn foreign_functions_are_equal_only_to_themselves() {
+        let a = Value::from_closure(Ok);
+        let b = Value::from_closure(Ok);
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn native_functions_check_their_argument_count() {
+        let function = Value::from_foreign(ForeignFunction::native(2, |arguments: Vec<Value>| {
+            Ok(arguments[0].clone() + arguments[1].clone())
+        }));
+        let arguments = vec![num("1"), num("2")];
+        assert_eq!(function.clone().call_foreign_function(list(&arguments)).unwrap(), num("3"));
+
+        let result = function.clone().call_foreign_function(list(&[num("1")])).unwrap();
+        assert_eq!(result.get_type(), Type::Problem(Problem::ArityError));
+        assert_eq!(result.problem_message(), Some("expected 2 arguments, found 1"));
+    }
+}
@@ -0,0 +1,310 @@
+// The textual assembly format for HLVM programs.
+//
+// A program is a sequence of items separated by whitespace:
+//
+//   "hey jude"      a string literal, supporting \n \t \r \0 \" and \\ escapes
+//   12  -3.5  1e3   a number literal
+//   none            the None value
+//   true  false     a boolean
+//   <>              an empty instance
+//   [ ... ]         a list literal containing the items inside it
+//   { ... }         a function literal containing the items inside it
+//   println         an instruction, named like `object::Instruction` (any case)
+//   # comment       ignored until the end of the line
+//
+// The whole program is wrapped in a function, which
+// can be given directly to `StackFrame::from_instructions`.
+// Brackets may be nested at most `MAX_NESTING` deep.
+
+use crate::value::*;
+use crate::object::*;
+use crate::literals::*;
+use crate::error::ParseError;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+
+// how deep brackets may be nested, so that parsing
+// a hostile program can't overflow the native stack
+pub const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open(char),       // `{` or `[`
+    Close(char),      // `}` or `]`
+    Str(String),      // a string literal with its escapes resolved
+    Word(String),     // a number, instruction or keyword
+    EmptyInstance,    // `<>`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Located {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+
+// parse an entire program into a function value
+pub fn parse(source: &str) -> Result<Value, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut position = 0;
+    let items = parse_sequence(&tokens, &mut position, None, 0)?;
+    Ok(fun(&items))
+}
+
+
+// walks over the source, keeping track of the line and column
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else if c.is_some() {
+            self.column += 1;
+        }
+        c
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError::new(self.line, self.column, message)
+    }
+}
+
+
+fn tokenize(source: &str) -> Result<Vec<Located>, ParseError> {
+    let mut tokens = vec![];
+    let mut cursor = Cursor::new(source);
+
+    while let Some(ch) = cursor.peek() {
+        let (line, column) = (cursor.line, cursor.column);
+
+        let token = match ch {
+            _ if ch.is_whitespace() => {
+                cursor.advance();
+                continue;
+            },
+
+            '#' => {
+                while let Some(c) = cursor.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    cursor.advance();
+                }
+                continue;
+            },
+
+            '{' | '[' => {
+                cursor.advance();
+                Token::Open(ch)
+            },
+
+            '}' | ']' => {
+                cursor.advance();
+                Token::Close(ch)
+            },
+
+            '<' => {
+                cursor.advance();
+                if cursor.advance() != Some('>') {
+                    return Err(ParseError::new(line, column, "expected `<>`"));
+                }
+                Token::EmptyInstance
+            },
+
+            '"' => {
+                cursor.advance();
+                let mut string = String::new();
+                loop {
+                    let escape = cursor.error("invalid escape sequence");
+                    match cursor.advance() {
+                        Some('"') => break,
+                        Some('\\') => string.push(match cursor.advance() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            _ => return Err(escape)
+                        }),
+                        Some(c) => string.push(c),
+                        None => return Err(ParseError::new(line, column, "unterminated string literal"))
+                    }
+                }
+                Token::Str(string)
+            },
+
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = cursor.peek() {
+                    if c.is_whitespace() || "{}[]<\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    cursor.advance();
+                }
+                Token::Word(word)
+            }
+        };
+
+        tokens.push(Located {token, line, column});
+    }
+
+    Ok(tokens)
+}
+
+
+// parse items until the closing bracket matching `opening`,
+// or until the end of the input if there is no opening bracket.
+// `depth` is the number of brackets around the sequence
+fn parse_sequence(tokens: &[Located], position: &mut usize, opening: Option<&Located>, depth: usize) -> Result<Vec<Value>, ParseError> {
+    let mut items = vec![];
+
+    while *position < tokens.len() {
+        let current = &tokens[*position];
+        *position += 1;
+
+        items.push(match &current.token {
+            Token::Open(bracket) => {
+                if depth == MAX_NESTING {
+                    let message = format!("brackets nested more than {} deep", MAX_NESTING);
+                    return Err(ParseError::new(current.line, current.column, &message));
+                }
+                let body = parse_sequence(tokens, position, Some(current), depth + 1)?;
+                if *bracket == '{' {
+                    fun(&body)
+                } else {
+                    list(&body)
+                }
+            },
+
+            Token::Close(bracket) => {
+                return match opening {
+                    Some(Located {token: Token::Open(o), ..}) if matching(*o) == *bracket => Ok(items),
+                    _ => Err(ParseError::new(current.line, current.column, &format!("unexpected `{}`", bracket)))
+                };
+            },
+
+            Token::Str(s) => string(s),
+            Token::EmptyInstance => empty_obj(),
+            Token::Word(word) => parse_word(word, current)?
+        });
+    }
+
+    match opening {
+        Some(o) => match o.token {
+            Token::Open(bracket) => Err(ParseError::new(o.line, o.column, &format!("unclosed `{}`", bracket))),
+            _ => Ok(items)
+        },
+        None => Ok(items)
+    }
+}
+
+fn matching(bracket: char) -> char {
+    match bracket {
+        '{' => '}',
+        '[' => ']',
+        _ => bracket
+    }
+}
+
+fn parse_word(word: &str, location: &Located) -> Result<Value, ParseError> {
+    if is_number(word) {
+        return match string_to_number(word.to_string()) {
+            Ok(n) => Ok(Value::from_number(n)),
+            Err(_) => Err(ParseError::new(location.line, location.column, &format!("invalid number `{}`", word)))
+        };
+    }
+
+    match word {
+        "none" => return Ok(none()),
+        "true" => return Ok(boolean(true)),
+        "false" => return Ok(boolean(false)),
+        _ => {}
+    }
+
+    match word.parse::<Instruction>() {
+        Ok(i) => Ok(ins(i)),
+        Err(_) => Err(ParseError::new(location.line, location.column, &format!("unknown instruction `{}`", word)))
+    }
+}
+
+// numbers start with a digit, optionally after a sign or decimal point
+fn is_number(word: &str) -> bool {
+    let unsigned = word.trim_start_matches(['+', '-']);
+    let unsigned = unsigned.strip_prefix('.').unwrap_or(unsigned);
+    unsigned.starts_with(|c: char| c.is_ascii_digit())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_parse_into_values() {
+        let program = parse("\"hi\\n\" -3.5 none true <> [1 2] { add } # ignored").unwrap();
+        assert_eq!(program, fun(&[
+            string("hi\n"),
+            Value::from_number(string_to_number("-3.5".to_string()).unwrap()),
+            none(),
+            boolean(true),
+            empty_obj(),
+            list(&[Value::from_number(1.into()), Value::from_number(2.into())]),
+            fun(&[ins(Instruction::Add)])
+        ]));
+    }
+
+    #[test]
+    fn instructions_are_case_insensitive() {
+        assert_eq!(parse("PrintLn").unwrap(), fun(&[ins(Instruction::Println)]));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = parse("1 2\n  frobnicate").unwrap_err();
+        assert_eq!((error.line(), error.column()), (2, 3));
+
+        let error = parse("[ 1 }").unwrap_err();
+        assert_eq!((error.line(), error.column()), (1, 5));
+
+        let error = parse("{ 1").unwrap_err();
+        assert_eq!((error.line(), error.column()), (1, 1));
+
+        assert!(parse("\"open").is_err());
+        assert!(parse("\"\\q\"").is_err());
+        assert!(parse("1x").is_err());
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth| format!("{}{}", "[ ".repeat(depth), "] ".repeat(depth));
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+
+        let error = parse(&nested(100_000)).unwrap_err();
+        assert_eq!((error.line(), error.column()), (1, 2 * MAX_NESTING + 1));
+        assert_eq!(error.message(), "brackets nested more than 256 deep");
+    }
+}
@@ -0,0 +1,164 @@
+// Native functions that the host makes available to programs by name.
+//
+// A program loads a native function with `Load`, just like a variable,
+// and runs it with `Call`, which pops the function's declared number of
+// arguments and pushes the value it returns:
+//
+//   16 "math.sqrt" load call println
+//
+// Variables take priority over native functions with the same name.
+
+use crate::value::*;
+use crate::object::*;
+use crate::table::Table;
+use crate::convert::NativeFunction;
+use crate::error::RuntimeError;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    functions: Table<Value>   // the native functions by name
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {functions: Table::new()}
+    }
+
+    // make a function with the given number of arguments
+    // available under a name, replacing any with the same name
+    pub fn register<F>(&mut self, name: &str, arity: usize, function: F)
+            where F: Fn(Vec<Value>) -> Result<Value, RuntimeError> + 'static {
+        self.functions.set(
+            name.to_string(),
+            Value::from_foreign(ForeignFunction::native(arity, function))
+        );
+    }
+
+    // register a rust function whose parameters and return value are
+    // converted with `convert::FromValue` and `convert::IntoValue`,
+    // taking as many arguments as it has parameters
+    pub fn register_fn<P, F>(&mut self, name: &str, function: F)
+            where F: NativeFunction<P> + 'static {
+        let arity = function.arity();
+        let prefix = name.to_string();
+        self.register(name, arity, move |arguments| {
+            function.call(arguments).map_err(|e| RuntimeError::new(
+                e.kind(),
+                &format!("{}: {}", prefix, e.message())
+            ))
+        });
+    }
+
+    // register functions named `module.name`
+    pub fn module(&mut self, name: &str) -> Module<'_> {
+        Module {
+            registry: self,
+            name: name.to_string()
+        }
+    }
+
+    // the native function with a given name
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.functions.get_ref(name).cloned()
+    }
+
+    // the names of every native function, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.functions.keys();
+        names.sort();
+        names
+    }
+}
+
+
+// registers functions under a common prefix
+pub struct Module<'a> {
+    registry: &'a mut Registry,
+    name: String
+}
+
+impl<'a> Module<'a> {
+    pub fn register<F>(&mut self, name: &str, arity: usize, function: F) -> &mut Self
+            where F: Fn(Vec<Value>) -> Result<Value, RuntimeError> + 'static {
+        self.registry.register(&format!("{}.{}", self.name, name), arity, function);
+        self
+    }
+
+    pub fn register_fn<P, F>(&mut self, name: &str, function: F) -> &mut Self
+            where F: NativeFunction<P> + 'static {
+        self.registry.register_fn(&format!("{}.{}", self.name, name), function);
+        self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::StackFrame;
+    use crate::parser::parse;
+    use crate::literals::*;
+
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry.module("math")
+            .register("half", 1, |arguments| Ok(arguments[0].clone() / num("2")))
+            .register("sub", 2, |arguments| Ok(arguments[0].clone() - arguments[1].clone()));
+        registry.register("answer", 0, |_| Ok(num("42")));
+        registry
+    }
+
+    fn run(source: &str) -> Vec<Value> {
+        let mut frame = StackFrame::from_instructions(parse(source).unwrap());
+        frame.set_registry(registry());
+        frame.run().unwrap();
+        frame.contents()
+    }
+
+    #[test]
+    fn programs_call_native_functions_by_name() {
+        assert_eq!(run("9 \"math.half\" load call"), [num("4.5")]);
+        assert_eq!(run("\"answer\" load call"), [num("42")]);
+    }
+
+    #[test]
+    fn arguments_are_given_in_the_order_they_were_pushed() {
+        assert_eq!(run("10 3 \"math.sub\" load call"), [num("7")]);
+    }
+
+    #[test]
+    fn variables_hide_native_functions() {
+        assert_eq!(run("{ 1 } \"answer\" store \"answer\" load call"), [num("1")]);
+    }
+
+    #[test]
+    fn calls_without_enough_arguments_are_problems() {
+        let result = run("3 \"math.sub\" load call");
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[1].get_type(), Type::Problem(Problem::ArityError));
+        assert_eq!(result[1].problem_message(), Some("expected 2 arguments, found 1"));
+    }
+
+    #[test]
+    fn names_are_sorted() {
+        assert_eq!(registry().names(), ["answer", "math.half", "math.sub"]);
+    }
+
+    #[test]
+    fn typed_functions_convert_their_arguments() {
+        let mut registry = Registry::new();
+        registry.module("text").register_fn("repeat", |s: &str, n: i64| s.repeat(n as usize));
+
+        let mut frame = StackFrame::from_instructions(parse("\"ab\" 3 \"text.repeat\" load call").unwrap());
+        frame.set_registry(registry.clone());
+        frame.run().unwrap();
+        assert_eq!(frame.contents(), [string("ababab")]);
+
+        let mut frame = StackFrame::from_instructions(parse("\"ab\" \"x\" \"text.repeat\" load call").unwrap());
+        frame.set_registry(registry);
+        let error = frame.run().unwrap_err();
+        assert_eq!(error.kind(), crate::error::ErrorKind::TypeMismatch);
+        assert!(error.message().starts_with("text.repeat: argument 2: "));
+    }
+}
@@ -0,0 +1,811 @@
+// Serde support for values, enabled with the `serde` cargo feature.
+//
+//   Num       an integer when it is one that fits in 128 bits,
+//             and otherwise an f64
+//   Bool      a boolean
+//   Str       a string
+//   List      an array
+//   Instance  a map from attribute names to values, sorted by name
+//   Map       a map, in key order
+//   Nothing   null
+//
+// Functions, instructions and problems have no serde equivalent, and
+// fail to serialize. A map whose keys are all strings is read as an
+// instance, and any other map as a Map, failing if one of its keys
+// can't be a map key. Errors are `RuntimeError`s with the TypeMismatch kind.
+//
+// Integers that fit in 128 bits round-trip exactly, and are never read
+// back through an f64. Other numbers are written as the nearest f64, so
+// decimals like 0.1 or 1E-300 that an f64 holds round-trip, and more
+// precise ones lose their last digits. Larger integers, NaN and the
+// infinities fail to serialize, as serde has no number that holds them.
+// Serializing `Exact(&value)` instead writes the numbers that no integer
+// or f64 holds exactly as strings of their digits, which read back as
+// strings.
+//
+// `to_value` and `from_value` convert between values and any other
+// type implementing `Serialize` or `Deserialize`, without going
+// through a text format:
+//
+//   let point: Point = from_value(value)?;
+
+use std::fmt::{self, Display};
+
+use serde::ser::{self, Serialize, Serializer, SerializeSeq, SerializeMap};
+use serde::de::{self, Deserialize, Deserializer, Visitor, IntoDeserializer, DeserializeOwned};
+use serde::de::value::{SeqDeserializer, MapDeserializer};
+use serde::forward_to_deserialize_any;
+
+use crate::value::*;
+use crate::object::*;
+use crate::literals::*;
+use crate::number::Number;
+use crate::table::Table;
+use crate::map::{Map, Key};
+use crate::disassembler::render;
+use crate::error::*;
+
+
+// convert any serializable rust value into a value
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, RuntimeError> {
+    value.serialize(ValueSerializer)
+}
+
+// convert a value into any deserializable rust value
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, RuntimeError> {
+    T::deserialize(value)
+}
+
+
+impl ser::Error for RuntimeError {
+    fn custom<T: Display>(message: T) -> Self {
+        RuntimeError::new(ErrorKind::TypeMismatch, &message.to_string())
+    }
+}
+
+impl de::Error for RuntimeError {
+    fn custom<T: Display>(message: T) -> Self {
+        RuntimeError::new(ErrorKind::TypeMismatch, &message.to_string())
+    }
+}
+
+
+// serializes a value, writing numbers that serde can only hold
+// approximately as strings of their digits instead
+pub struct Exact<'a>(pub &'a Value);
+
+impl Serialize for Exact<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialized {value: self.0, exact: true}.serialize(serializer)
+    }
+}
+
+
+fn instance(table: Table<Value>) -> Value {
+    let mut result = empty_obj();
+    result.set_attributes(table);
+    result
+}
+
+// an instance if every key is a string, and otherwise a map
+fn from_entries(entries: Vec<(Value, Value)>) -> Result<Value, String> {
+    if entries.iter().all(|(k, _)| k.get_type() == Type::Str) {
+        let mut table = Table::new();
+        for (k, v) in entries {
+            table.set(k.as_string(), v);
+        }
+        return Ok(instance(table));
+    }
+
+    let mut map = Map::new();
+    for (k, v) in entries {
+        match Key::from_object(&k) {
+            Some(key) => map.insert(key, v),
+            None => return Err(format!("{} can not be used as a map key", render(&k)))
+        };
+    }
+    Ok(Value::from_map(map))
+}
+
+// the sorted attributes of an instance
+fn attributes(value: &Value) -> Vec<(String, Value)> {
+    let mut attributes: Vec<(String, Value)> = value.get_attributes().iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    attributes
+}
+
+fn unsupported(value: &Value) -> String {
+    format!("{:?} values have no serde equivalent", value.get_type())
+}
+
+// a number the way serde sees it
+enum Written {
+    Signed(i64),
+    Unsigned(u64),
+    Signed128(i128),
+    Unsigned128(u128),
+    Float(f64),
+    Text(String),
+}
+
+// how to write a number, with `exact` saying whether numbers
+// that serde can't hold exactly are written as strings
+fn written(number: &Number, exact: bool) -> Result<Written, String> {
+    if number.is_nan() || number.is_infinite() {
+        return Err(format!("{} has no serde equivalent", number));
+    }
+    if let Some(n) = number.to_i64() {
+        return Ok(Written::Signed(n));
+    }
+    if let Some(n) = number.to_u64() {
+        return Ok(Written::Unsigned(n));
+    }
+    if let Some(n) = number.to_i128() {
+        return Ok(Written::Signed128(n));
+    }
+    if let Some(n) = number.to_u128() {
+        return Ok(Written::Unsigned128(n));
+    }
+
+    // the shortest form of an f64 reads back as the same number if the f64 is exact
+    let float = number.to_f64();
+    let held = format!("{:e}", float).parse::<Number>().is_ok_and(|n| n == *number);
+    if held && !number.is_integer() {
+        return Ok(Written::Float(float));
+    }
+    if exact {
+        return Ok(Written::Text(number.to_string()));
+    }
+    if number.is_integer() {
+        return Err(format!("{} is too large for a 128 bit integer", number));
+    }
+    Ok(Written::Float(float))
+}
+
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialized {value: self, exact: false}.serialize(serializer)
+    }
+}
+
+// a value along with how its numbers are written,
+// which is passed down to the values inside it
+struct Serialized<'a> {
+    value: &'a Value,
+    exact: bool
+}
+
+impl Serialized<'_> {
+    fn inner<'a>(&self, value: &'a Value) -> Serialized<'a> {
+        Serialized {value, exact: self.exact}
+    }
+}
+
+impl Serialize for Serialized<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = self.value;
+        match value.get_type() {
+            Type::Nothing => serializer.serialize_unit(),
+            Type::Str => serializer.serialize_str(value.get_string()),
+            Type::Bool => serializer.serialize_bool(value.as_bool()),
+            Type::Num => match written(&value.as_number(), self.exact).map_err(ser::Error::custom)? {
+                Written::Signed(n) => serializer.serialize_i64(n),
+                Written::Unsigned(n) => serializer.serialize_u64(n),
+                Written::Signed128(n) => serializer.serialize_i128(n),
+                Written::Unsigned128(n) => serializer.serialize_u128(n),
+                Written::Float(n) => serializer.serialize_f64(n),
+                Written::Text(s) => serializer.serialize_str(&s)
+            },
+            Type::List => {
+                let mut seq = serializer.serialize_seq(Some(value.get_list().len()))?;
+                for item in value.get_list() {
+                    seq.serialize_element(&self.inner(item))?;
+                }
+                seq.end()
+            },
+            Type::Instance => {
+                let attributes = attributes(value);
+                let mut map = serializer.serialize_map(Some(attributes.len()))?;
+                for (k, v) in &attributes {
+                    map.serialize_entry(k, &self.inner(v))?;
+                }
+                map.end()
+            },
+            Type::Map => {
+                let mut map = serializer.serialize_map(Some(value.get_map().len()))?;
+                for (k, v) in value.get_map().iter() {
+                    map.serialize_entry(&self.inner(&k.to_object()), &self.inner(v))?;
+                }
+                map.end()
+            },
+            Type::Function | Type::Command(_) | Type::Problem(_) => {
+                Err(ser::Error::custom(unsupported(value)))
+            }
+        }
+    }
+}
+
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number, boolean, string, array, map or null")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(boolean(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::from_number(Number::from(n)))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Value, E> {
+        Ok(Value::from_number(Number::from(n)))
+    }
+
+    fn visit_i128<E>(self, n: i128) -> Result<Value, E> {
+        Ok(Value::from_number(Number::from(n)))
+    }
+
+    fn visit_u128<E>(self, n: u128) -> Result<Value, E> {
+        Ok(Value::from_number(Number::from(n)))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::from_f64(n))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(string(s))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::from_string(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(none())
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(none())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::from_vector(items))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = vec![];
+        while let Some(entry) = map.next_entry::<Value, Value>()? {
+            entries.push(entry);
+        }
+        from_entries(entries).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+
+// builds values out of rust values, for `to_value`
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = VariantSerializer<ListSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = InstanceSerializer;
+    type SerializeStructVariant = VariantSerializer<InstanceSerializer>;
+
+    fn serialize_bool(self, b: bool) -> Result<Value, RuntimeError> {
+        Ok(boolean(b))
+    }
+
+    fn serialize_i8(self, n: i8) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_i16(self, n: i16) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_i32(self, n: i32) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_i64(self, n: i64) -> Result<Value, RuntimeError> {
+        Ok(Value::from_number(Number::from(n)))
+    }
+
+    fn serialize_u8(self, n: u8) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_u16(self, n: u16) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_u32(self, n: u32) -> Result<Value, RuntimeError> {
+        self.serialize_i64(n as i64)
+    }
+
+    fn serialize_u64(self, n: u64) -> Result<Value, RuntimeError> {
+        ValueVisitor.visit_u64(n)
+    }
+
+    fn serialize_i128(self, n: i128) -> Result<Value, RuntimeError> {
+        ValueVisitor.visit_i128(n)
+    }
+
+    fn serialize_u128(self, n: u128) -> Result<Value, RuntimeError> {
+        ValueVisitor.visit_u128(n)
+    }
+
+    fn serialize_f32(self, n: f32) -> Result<Value, RuntimeError> {
+        self.serialize_f64(n as f64)
+    }
+
+    fn serialize_f64(self, n: f64) -> Result<Value, RuntimeError> {
+        Ok(Value::from_f64(n))
+    }
+
+    fn serialize_char(self, c: char) -> Result<Value, RuntimeError> {
+        Ok(Value::from_string(c.to_string()))
+    }
+
+    fn serialize_str(self, s: &str) -> Result<Value, RuntimeError> {
+        Ok(string(s))
+    }
+
+    fn serialize_bytes(self, bytes: &[u8]) -> Result<Value, RuntimeError> {
+        Ok(Value::from_vector(bytes.iter().map(|b| Value::from_number(Number::from(*b as i32))).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Value, RuntimeError> {
+        Ok(none())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, RuntimeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, RuntimeError> {
+        Ok(none())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, RuntimeError> {
+        Ok(none())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Value, RuntimeError> {
+        Ok(string(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Value, RuntimeError> {
+        value.serialize(self)
+    }
+
+    // enum variants with data are instances with the variant as their only attribute
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T) -> Result<Value, RuntimeError> {
+        let mut table = Table::new();
+        table.set(variant.to_string(), to_value(value)?);
+        Ok(instance(table))
+    }
+
+    fn serialize_seq(self, length: Option<usize>) -> Result<ListSerializer, RuntimeError> {
+        Ok(ListSerializer {items: Vec::with_capacity(length.unwrap_or(0))})
+    }
+
+    fn serialize_tuple(self, length: usize) -> Result<ListSerializer, RuntimeError> {
+        self.serialize_seq(Some(length))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, length: usize) -> Result<ListSerializer, RuntimeError> {
+        self.serialize_seq(Some(length))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, length: usize) -> Result<VariantSerializer<ListSerializer>, RuntimeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(length))?
+        })
+    }
+
+    fn serialize_map(self, length: Option<usize>) -> Result<MapSerializer, RuntimeError> {
+        Ok(MapSerializer {entries: Vec::with_capacity(length.unwrap_or(0)), key: None})
+    }
+
+    fn serialize_struct(self, _name: &'static str, _length: usize) -> Result<InstanceSerializer, RuntimeError> {
+        Ok(InstanceSerializer {table: Table::new()})
+    }
+
+    fn serialize_struct_variant(self, name: &'static str, _index: u32, variant: &'static str, length: usize) -> Result<VariantSerializer<InstanceSerializer>, RuntimeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_struct(name, length)?
+        })
+    }
+}
+
+
+struct ListSerializer {
+    items: Vec<Value>
+}
+
+impl SerializeSeq for ListSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        self.items.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        Ok(Value::from_vector(self.items))
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+
+// maps with string keys become instances, and other maps become maps
+struct MapSerializer {
+    entries: Vec<(Value, Value)>,
+    key: Option<Value>   // the key of the entry whose value is next
+}
+
+impl SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), RuntimeError> {
+        self.key = Some(to_value(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        match self.key.take() {
+            Some(key) => {
+                self.entries.push((key, to_value(value)?));
+                Ok(())
+            },
+            None => Err(ser::Error::custom("map value serialized before its key"))
+        }
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        from_entries(self.entries).map_err(ser::Error::custom)
+    }
+}
+
+// structs become instances, with an attribute for each field
+struct InstanceSerializer {
+    table: Table<Value>
+}
+
+impl ser::SerializeStruct for InstanceSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError> {
+        self.table.set(key.to_string(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        Ok(instance(self.table))
+    }
+}
+
+
+// wraps the value of an enum variant in an instance named after the variant
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S
+}
+
+impl<S> VariantSerializer<S> {
+    fn wrap(variant: &str, value: Value) -> Value {
+        let mut table = Table::new();
+        table.set(variant.to_string(), value);
+        instance(table)
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<ListSerializer> {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        Ok(Self::wrap(self.variant, SerializeSeq::end(self.inner)?))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<InstanceSerializer> {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        Ok(Self::wrap(self.variant, ser::SerializeStruct::end(self.inner)?))
+    }
+}
+
+
+// reads rust values out of values, for `from_value`
+impl<'de> Deserializer<'de> for Value {
+    type Error = RuntimeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        match self.get_type() {
+            Type::Nothing => visitor.visit_unit(),
+            Type::Str => visitor.visit_string(self.as_string()),
+            Type::Bool => visitor.visit_bool(self.as_bool()),
+            Type::Num => match written(&self.as_number(), false).map_err(de::Error::custom)? {
+                Written::Signed(n) => visitor.visit_i64(n),
+                Written::Unsigned(n) => visitor.visit_u64(n),
+                Written::Signed128(n) => visitor.visit_i128(n),
+                Written::Unsigned128(n) => visitor.visit_u128(n),
+                Written::Float(n) => visitor.visit_f64(n),
+                Written::Text(s) => visitor.visit_string(s)
+            },
+            Type::List => {
+                let mut seq = SeqDeserializer::new(self.as_list().into_iter());
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            },
+            Type::Instance => {
+                let mut map = MapDeserializer::new(attributes(&self).into_iter());
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            },
+            Type::Map => {
+                let entries: Vec<(Value, Value)> = self.get_map().iter()
+                    .map(|(k, v)| (k.to_object(), v.clone()))
+                    .collect();
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            },
+            Type::Function | Type::Command(_) | Type::Problem(_) => {
+                Err(de::Error::custom(unsupported(&self)))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        match self.get_type() {
+            Type::Nothing => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, RuntimeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // unit variants are strings, and variants with data are
+    // instances with the variant as their only attribute
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, RuntimeError> {
+        match self.get_type() {
+            Type::Str => visitor.visit_enum(self.as_string().into_deserializer()),
+            Type::Instance => {
+                let mut attributes = attributes(&self);
+                if attributes.len() != 1 {
+                    return Err(de::Error::custom("enum variants with data must be instances with one attribute"));
+                }
+                let (variant, value) = attributes.remove(0);
+                visitor.visit_enum(EnumDeserializer {variant, value})
+            },
+            _ => Err(de::Error::custom(format!("expected an enum variant, found {:?}", self.get_type())))
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, RuntimeError> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+
+struct EnumDeserializer {
+    variant: String,
+    value: Value
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = RuntimeError;
+    type Variant = Value;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Value), RuntimeError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+// the data of an enum variant
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = RuntimeError;
+
+    fn unit_variant(self) -> Result<(), RuntimeError> {
+        Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, RuntimeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _length: usize, visitor: V) -> Result<V::Value, RuntimeError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, RuntimeError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn map(entries: &[(Value, Value)]) -> Value {
+        let mut map = Map::new();
+        for (k, v) in entries {
+            map.insert(Key::from_object(k).unwrap(), v.clone());
+        }
+        Value::from_map(map)
+    }
+
+    // serialize a value and read it back
+    fn round_trip(value: &Value) -> Value {
+        from_value(to_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut point = empty_obj();
+        point.set_attr("x".to_string(), num("1"));
+        point.set_attr("tags".to_string(), list(&[string("a"), boolean(true), none()]));
+        assert_eq!(round_trip(&point), point);
+    }
+
+    #[test]
+    fn numbers_that_serde_holds_round_trip() {
+        for n in ["0.1", "-2.5", "1E-300", "-170141183460469231731687303715884105728"] {
+            assert_eq!(round_trip(&num(n)), num(n));
+        }
+
+        // integers are read back as integers, not through an f64
+        let largest = num("340282366920938463463374607431768211455");
+        assert_eq!(to_value(&largest).unwrap().as_number().to_u128(), Some(u128::MAX));
+        assert!(round_trip(&largest).as_number().is_integer());
+    }
+
+    #[test]
+    fn other_numbers_are_approximated_or_fail() {
+        let precise = num("1.234567890123456789012345678901234");
+        assert_eq!(round_trip(&precise), num("1.2345678901234567"));
+
+        let huge = "1606938044258990275541962092341162602522202993782792835301376";
+        for n in [huge, "NaN", "-Infinity"] {
+            assert_eq!(to_value(&num(n)).unwrap_err().kind(), ErrorKind::TypeMismatch);
+            assert!(from_value::<f64>(num(n)).is_err());
+        }
+    }
+
+    #[test]
+    fn exact_numbers_are_written_as_strings() {
+        let precise = "1.234567890123456789012345678901234";
+        let huge = "1606938044258990275541962092341162602522202993782792835301376";
+        let value = list(&[num(precise), num(huge), num("0.5"), num("7")]);
+        assert_eq!(
+            to_value(&Exact(&value)).unwrap(),
+            list(&[string(precise), string(huge), num("0.5"), num("7")])
+        );
+        assert!(to_value(&Exact(&num("NaN"))).is_err());
+    }
+
+    #[test]
+    fn maps_with_other_keys_round_trip() {
+        let numbered = map(&[(num("1"), string("one")), (list(&[boolean(true)]), none())]);
+        assert_eq!(round_trip(&numbered), numbered);
+
+        let named = map(&[(string("a"), num("1"))]);
+        let mut instance = empty_obj();
+        instance.set_attr("a".to_string(), num("1"));
+        assert_eq!(round_trip(&named), instance);
+    }
+
+    #[test]
+    fn maps_with_keys_that_cannot_be_map_keys_fail() {
+        let entries = vec![(none(), num("1"))];
+        let error = Value::deserialize(MapDeserializer::<_, RuntimeError>::new(entries.into_iter())).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(error.message(), "none can not be used as a map key");
+    }
+
+    #[test]
+    fn rust_values_convert() {
+        let mut scores = HashMap::new();
+        scores.insert("ada".to_string(), vec![1i64, 2]);
+        let value = to_value(&scores).unwrap();
+        assert_eq!(from_value::<HashMap<String, Vec<i64>>>(value).unwrap(), scores);
+        assert_eq!(from_value::<(String, Option<f64>)>(list(&[string("x"), none()])).unwrap(), ("x".to_string(), None));
+    }
+
+    #[test]
+    fn functions_have_no_serde_equivalent() {
+        let error = to_value(&fun(&[])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+    }
+}
@@ -3,6 +3,18 @@ use crate::object::*;
 use crate::value::*;
 use crate::literals::*;
 use crate::table::Table;
+use crate::map::{Map, Key};
+use crate::math;
+use crate::format;
+use crate::disassembler::render;
+use crate::debugger::{Hook, Context, Control};
+use crate::registry::Registry;
+
+use std::fmt::{Debug, Formatter};
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Instant;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Pair<A, B> {
@@ -11,245 +23,659 @@ pub struct Pair<A, B> {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Scope {
+struct Variables {
     table: Table<Pair<Value, Scope>>, // the variables in scope
-    outer_scope: Option<Box<Scope>>   // the pointer to the parent scope
+    outer_scope: Option<Scope>        // the parent scope
+}
+
+// scopes are shared by every value pushed while they were current,
+// and are only copied when a variable is defined in a shared scope
+#[derive(Debug, Clone)]
+pub struct Scope {
+    variables: Rc<Variables>
+}
+
+impl PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.variables, &other.variables) || self.variables == other.variables
+    }
 }
 
 impl Scope {
     // create a new scope from a parent scope
-    pub fn new(outer_scope: Option<Box<Scope>>) -> Self {
-        return Self {
-            table: Table::new(),
-            outer_scope
+    pub fn new(outer_scope: Option<Scope>) -> Self {
+        Self {
+            variables: Rc::new(Variables {
+                table: Table::new(),
+                outer_scope
+            })
         }
     }
 
+    // the scope variables are looked up in when they aren't in this one
+    pub fn outer(&self) -> Option<&Scope> {
+        self.variables.outer_scope.as_ref()
+    }
+
+    // the variables defined directly in this scope, sorted by name
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let table = &self.variables.table;
+        let mut names = table.keys();
+        names.sort();
+        names.into_iter()
+            .filter_map(|name| table.get(name.clone()).map(|v| (name, v.first)))
+            .collect()
+    }
+
     // define the value of the variable in this scope
     fn define(&mut self, name: String, object: Pair<Value, Scope>) {
-        self.table.set(name, object)
-    }
-
-    // get the value of the variable in this scope
-    fn get(&mut self, name: String) -> Pair<Value, Scope> {
-        let value_scope = match self.table.get(name.clone()) {
-            Some(v) => return v,
-            None => {
-                // the value isnt in this scope
-                // try to use the parent scope if possible, 
-                // otherwise just return an empty value and scope
-                match &mut self.outer_scope {
-                    Some(v) => (*v).get(name),
-                    None => Pair{first: Value::from_nothing(), second: Scope::new(None)}
-                }
+        Rc::make_mut(&mut self.variables).table.set(name, object)
+    }
+
+    // get the value of the variable in this scope,
+    // or in the parent scopes if it isnt in this one
+    fn get(&self, name: &str) -> Option<Pair<Value, Scope>> {
+        let mut scope = self;
+        loop {
+            match scope.variables.table.get_ref(name) {
+                Some(v) => return Some(v.clone()),
+                None => scope = scope.outer()?
             }
-        };
+        }
+    }
+
+    // does this scope have no variables and no parent?
+    fn is_empty(&self) -> bool {
+        self.variables.table.is_empty() && self.variables.outer_scope.is_none()
+    }
+
+    // is this the very same scope, rather than an equal copy?
+    // comparing the variables themselves is much slower
+    fn is(&self, other: &Scope) -> bool {
+        Rc::ptr_eq(&self.variables, &other.variables)
+    }
+}
+
+// comparing with a problem results in the problem, the same way
+// arithmetic does, checking the topmost value first
+fn first_problem(a: &Value, b: &Value) -> Option<Value> {
+    if a.is_problem() {
+        return Some(a.clone());
+    }
+    if b.is_problem() {
+        return Some(b.clone());
+    }
+    None
+}
+
+// the problem that comparing two values by size gives: the
+// first problem operand, or a TypeError if either isn't a number
+fn ordering_problem(a: &Value, b: &Value) -> Option<Value> {
+    first_problem(a, b).or_else(|| {
+        [a, b].iter().find(|v| v.get_type() != Type::Num).map(|v| Value::from_problem_with(
+            Problem::TypeError,
+            &format!("cannot compare the size of {:?} values", v.get_type()),
+            None
+        ))
+    })
+}
+
+// what the problem instructions give for a value that isn't a problem
+fn not_a_problem(value: &Value) -> Value {
+    Value::from_problem_with(
+        Problem::TypeError,
+        &format!("expected a problem, found a {:?} value", value.get_type()),
+        Some(value.clone())
+    )
+}
+
+// the value a handler is called with: the value that was raised,
+// or a RuntimeError problem for an error, with the kind of the
+// error as its payload
+fn caught(error: RuntimeError) -> Value {
+    match error.raised() {
+        Some(value) => value.clone(),
+        None => Value::from_problem_with(
+            Problem::RuntimeError,
+            error.message(),
+            Some(string(&format!("{:?}", error.kind())))
+        )
+    }
+}
+
+// the problem with a value that should be a map, if there is one
+fn expect_map(map: &Value) -> Result<(), Value> {
+    match map.get_type() {
+        Type::Map => Ok(()),
+        Type::Problem(_) => Err(map.clone()),
+        t => Err(Value::from_problem_with(
+            Problem::TypeError,
+            &format!("expected a map, found a {:?} value", t),
+            Some(map.clone())
+        ))
+    }
+}
 
-        match value_scope.first.get_type() {
-            Type::Function => value_scope,
-            _ => Pair{first: value_scope.first, second: Scope::new(None)}
+// the key to look up in a map, or the problem with the map or the key
+fn map_key(map: &Value, key: &Value) -> Result<Key, Value> {
+    expect_map(map)?;
+    if key.is_problem() {
+        return Err(key.clone());
+    }
+    Key::from_object(key).ok_or_else(|| Value::from_problem_with(
+        Problem::TypeError,
+        &format!("{} can not be used as a map key", render(key)),
+        Some(key.clone())
+    ))
+}
+
+// a hook shared by a stackframe and every stackframe it calls
+#[derive(Clone)]
+struct SharedHook(Rc<RefCell<dyn Hook>>);
+
+impl Debug for SharedHook {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Hook")
+    }
+}
+
+// limits on the resources a program may use, each unlimited when None.
+// by default only the call depth is limited, so that a function that
+// calls itself forever fails instead of using up all of the memory
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Limits {
+    pub max_call_depth: Option<usize>,     // how deeply calls may be nested
+    pub max_stack_length: Option<usize>,   // how many values the stack may hold, across all calls
+    pub max_value_size: Option<usize>,     // how large a list or string may grow, in `Object::size` cells
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_stack_length: None,
+            max_value_size: None
         }
     }
 }
 
+// the deepest calls may be nested unless the limits say otherwise
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// the part of the stack that belongs to one call
+#[derive(Clone, Debug)]
+struct Frame {
+    scope: Scope,   // scope used to store variables
+    base: usize,    // where the call's own values start on the stack
+}
+
+// work left to do for the instructions being run. calls and loops push
+// tasks instead of recursing, so nesting them only uses heap memory
+#[derive(Clone, Debug)]
+enum Task {
+    // run the instructions of a function (or list) from an offset,
+    // marking errors with the offset of the instruction that failed
+    Run {instructions: Value, index: usize, annotate: bool},
+    // leave the current call once its instructions have run
+    Return,
+    // run a while loop, checking the condition's result when `check` is set
+    Loop {condition: Value, body: Value, check: bool},
+    // call a handler if a task above this one fails with a catchable error,
+    // first putting back the stack and call bases from before the body ran
+    Catch {handler: Pair<Value, Scope>, contents: Vec<Pair<Value, Scope>>, bases: Vec<usize>},
+    // turn the result of the right side of `andthen` or `orelse` into a boolean
+    Decide,
+}
+
+// a stackframe runs a function, along with every function it calls.
+// all of the calls share one stack: a called function pushes its values
+// on top of its caller's, and popping past its own values takes
+// its arguments from the caller, so nothing is copied for a call
 #[derive(Clone, Debug)]
 pub struct StackFrame {
-    scope: Scope,                        // scope used to store variables
     contents: Vec<Pair<Value, Scope>>,   // stores the contents of the stack
+    frames: Vec<Frame>,                  // the calls being run, outermost first
+    tasks: Vec<Task>,                    // the work left to do, innermost last
     instructions: Value,                 // the instructions to run (a function or a list)
-    number_of_args_taken: i32,           // used to count how many arguments a stack used
-    outer_stack: Option<Box<StackFrame>> // the parent stack
+    registry: Registry,                  // the native functions programs can load
+    limits: Limits,                      // the most resources the program may use
+    trace: bool,                         // print each instruction before it runs
+    steps: usize,                        // instructions run so far, including in called functions
+    fuel: Option<usize>,                 // how many more instructions are allowed to run
+    deadline: Option<Instant>,           // the time the program must finish by
+    hook: Option<SharedHook>             // called before each instruction runs
 }
 
 impl StackFrame {
-    // create stackframe from a parent stackframe / scope
-    pub fn new(outer_stack: Option<Box<StackFrame>>, scope: Scope, instructions: Value) -> Self {
-        return Self {
-            scope,
+    // create stackframe that stores its variables in a scope
+    pub fn new(scope: Scope, instructions: Value) -> Self {
+        Self {
             contents: vec![],
+            frames: vec![Frame {scope, base: 0}],
+            tasks: vec![],
             instructions,
-            number_of_args_taken: 0,
-            outer_stack
+            registry: Registry::new(),
+            limits: Limits::default(),
+            trace: false,
+            steps: 0,
+            fuel: None,
+            deadline: None,
+            hook: None
         }
     }
 
     // create a new stackframe from a function
     pub fn from_instructions(instructions: Value) -> Self {
-        Self {
-            scope: Scope::new(None),
-            contents: vec![],
-            instructions,
-            number_of_args_taken: 0,
-            outer_stack: None
-        }
+        Self::new(Scope::new(None), instructions)
+    }
+
+    // the native functions programs can load by name
+    pub fn set_registry(&mut self, registry: Registry) {
+        self.registry = registry;
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    // limit the resources the program may use
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // print each instruction to stderr before it runs
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // fail with OutOfFuel after running this many more instructions
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        self.fuel = fuel;
+    }
+
+    // the number of instructions left to run, if the fuel is limited
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+
+    // fail with OutOfFuel if an instruction starts after this time
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
     }
 
-    pub fn return_value(&mut self) -> Value {
+    // call a hook before each instruction runs, such as a `debugger::Debugger`
+    pub fn set_hook(&mut self, hook: Option<Rc<RefCell<dyn Hook>>>) {
+        self.hook = hook.map(SharedHook);
+    }
+
+    // the number of instructions run so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn return_value(&mut self) -> Result<Value, RuntimeError> {
         self.pop_value()
     }
 
+    // the values the current call has on the stack, from the bottom to the top
+    pub fn contents(&self) -> Vec<Value> {
+        self.current().iter().map(|v| v.first.clone()).collect()
+    }
+
+    // the scope the current call stores its variables in
+    pub fn scope(&self) -> &Scope {
+        &self.frame().scope
+    }
+
+    // every scope a variable could be loaded from, innermost first
+    pub fn scopes(&self) -> Vec<&Scope> {
+        let mut scopes = vec![];
+        for frame in self.frames.iter().rev() {
+            let mut scope = Some(&frame.scope);
+            while let Some(s) = scope {
+                scopes.push(s);
+                scope = s.outer();
+            }
+        }
+        scopes
+    }
+
+    // how many calls the current one is nested inside of
+    pub fn depth(&self) -> usize {
+        self.frames.len() - 1
+    }
+
     // run a stackframe
-    pub fn run(&mut self) {
-        // println!("stack {:?}", match self.outer_stack.clone().contents{
-        //     Some
-        // });
-        for instruction in self.instructions.as_list() {
-            self.step(instruction)
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.execute(self.instructions.clone())
+    }
+
+    // run more instructions (a function or a list) on this stackframe,
+    // keeping the stack and variables left behind by earlier instructions
+    pub fn execute(&mut self, instructions: Value) -> Result<(), RuntimeError> {
+        let floor = self.tasks.len();
+        self.tasks.push(Task::Run {instructions, index: 0, annotate: true});
+        self.drive(floor)
+    }
+
+    // run a single instruction on this stackframe,
+    // along with any function it calls
+    pub fn step(&mut self, instruction: Value) -> Result<(), RuntimeError> {
+        let floor = self.tasks.len();
+        if let Err(e) = self.dispatch(instruction) {
+            self.unwind(e, floor)?;
+        }
+        self.drive(floor)
+    }
+
+    // work through the tasks above `floor` until there are none left
+    fn drive(&mut self, floor: usize) -> Result<(), RuntimeError> {
+        while self.tasks.len() > floor {
+            if let Err(e) = self.advance() {
+                self.unwind(e, floor)?;
+            }
+        }
+        Ok(())
+    }
+
+    // do the next piece of the innermost task
+    fn advance(&mut self) -> Result<(), RuntimeError> {
+        match self.tasks.last_mut().expect("there is a task to advance") {
+            Task::Run {instructions, index, ..} => {
+                let instruction = match instructions.get_list().get(*index) {
+                    Some(i) => i.clone(),
+                    None => {
+                        self.tasks.pop();
+                        return Ok(());
+                    }
+                };
+                *index += 1;
+
+                if self.hook.is_some() {
+                    let (function, offset) = (instructions.clone(), *index - 1);
+                    self.step_at(&function, offset, instruction)
+                } else {
+                    self.dispatch(instruction)
+                }
+            },
+
+            Task::Return => {
+                self.tasks.pop();
+                self.frames.pop().expect("the called function's frame is still on the stack");
+                Ok(())
+            },
+
+            // every iteration costs fuel, even if the loop is empty
+            Task::Loop {condition, check: check @ false, ..} => {
+                *check = true;
+                let condition = condition.clone();
+                self.consume_fuel()?;
+                self.tasks.push(Task::Run {instructions: condition, index: 0, annotate: false});
+                Ok(())
+            },
+
+            Task::Loop {body, check, ..} => {
+                *check = false;
+                let body = body.clone();
+                if self.pop_value()?.is_truthy() {
+                    self.tasks.push(Task::Run {instructions: body, index: 0, annotate: false});
+                } else {
+                    self.tasks.pop();
+                }
+                Ok(())
+            },
+
+            // the body of a `try` finished without failing
+            Task::Catch {..} => {
+                self.tasks.pop();
+                Ok(())
+            },
+
+            Task::Decide => {
+                self.tasks.pop();
+                let result = self.pop_value()?;
+                if result.is_problem() {
+                    self.push_value(result)
+                } else {
+                    self.push_value(boolean(result.is_truthy()))
+                }
+            }
+        }
+    }
+
+    // throw away the tasks above `floor` that an error stops, until
+    // a `try` catches it. calls that fail return nothing
+    fn unwind(&mut self, mut error: RuntimeError, floor: usize) -> Result<(), RuntimeError> {
+        while self.tasks.len() > floor {
+            match self.tasks.pop().expect("there is a task to unwind") {
+                Task::Run {index, annotate: true, ..} if index > 0 => {
+                    error = error.at_instruction(index - 1);
+                },
+
+                Task::Return => {
+                    let frame = self.frames.pop().expect("the called function's frame is still on the stack");
+                    self.contents.truncate(frame.base);
+                },
+
+                Task::Catch {handler, contents, bases} if error.is_catchable() => {
+                    self.contents = contents;
+                    for (frame, base) in self.frames.iter_mut().zip(bases) {
+                        frame.base = base;
+                    }
+                    let handled = self.push_value(caught(error))
+                        .and_then(|()| self.call(handler));
+                    match handled {
+                        Ok(()) => return Ok(()),
+                        Err(e) => error = e
+                    }
+                },
+
+                _ => {}
+            }
+        }
+        Err(error)
+    }
+
+    // run the instruction at an offset in a function, letting the hook see it first
+    fn step_at(&mut self, function: &Value, index: usize, instruction: Value) -> Result<(), RuntimeError> {
+        if let Some(hook) = self.hook.clone() {
+            let context = Context::new(self, function, index, &instruction);
+            if hook.0.borrow_mut().before_instruction(&context) == Control::Abort {
+                return Err(self.error(ErrorKind::Aborted, "Aborted by hook"));
+            }
         }
+        self.dispatch(instruction)
     }
 
-    fn step(&mut self, instruction: Value) {
+    // start running a single instruction. instructions that call
+    // functions or loop leave tasks behind to finish the work
+    fn dispatch(&mut self, instruction: Value) -> Result<(), RuntimeError> {
+        self.consume_fuel()?;
+        self.steps += 1;
+
+        if self.trace {
+            eprintln!("[trace] {} {}", self.depth(), render(&instruction));
+        }
+
         match instruction.as_instruction() {
             // print the topmost object without a carriage return
-            Instruction::Print => self.pop_value().print(),
+            Instruction::Print => self.pop_value()?.print(),
             // print the topmost object with a carriage return
-            Instruction::Println => self.pop_value().println(),
+            Instruction::Println => self.pop_value()?.println(),
 
             // call the topmost object on the stack as
             // a function (as if it were in this scope)
             // until it returns 0
             Instruction::While => {
-                let condition = self.pop_value();
-                let body = self.pop_value();
-                self.while_function(condition, body);
+                let condition = self.pop_value()?;
+                let body = self.pop_value()?;
+                self.tasks.push(Task::Loop {condition, body, check: false});
             },
 
             Instruction::If => {
                 // println!("{:#?}", self.contents);
-                let c = self.pop_value();
-                let a = self.pop();
-                let b = self.pop();
+                let c = self.pop_value()?;
+                let a = self.pop()?;
+                let b = self.pop()?;
                 // println!("{} {} {}", c, a.first, b.first);
-                if c != num("0") && c != none() {
-                    self.push(a);
+                if c.is_truthy() {
+                    self.push(a)?;
                 } else {
-                    self.push(b);
+                    self.push(b)?;
                 }
             },
 
             Instruction::ListFromStack => {
                 let mut l = list(&[]);
 
-                for _ in 0..self.contents.len() {
-                    l.list_push(self.pop_value());
+                for _ in 0..self.current().len() {
+                    let value = self.pop_value()?;
+                    self.list_push(&mut l, value)?;
                 }
 
-                self.push_value(l);
+                self.push_value(l)?;
             },
 
             Instruction::Append => {
-                let mut list = self.pop_value();
-                let value = self.pop_value();
-                list.list_push(value);
-                self.push_value(list);
+                let mut list = self.pop_value()?;
+                let value = self.pop_value()?;
+                self.list_push(&mut list, value)?;
+                self.push_value(list)?;
             },
 
             Instruction::Pop => {
-                let mut list = self.pop_value();
+                let mut list = self.pop_value()?;
                 let value = list.list_pop();
-                self.push_value(value);
+                self.push_value(value)?;
             },
             
             Instruction::Index => {
-                let mut list = self.pop_value();
-                let index = self.pop_value();
-                self.push_value(list.index(index));
+                let mut list = self.pop_value()?;
+                let index = self.pop_value()?;
+                self.push_value(list.index(index))?;
+            },
+
+            // slice the topmost list or string, taking the step,
+            // end and start below it, any of which may be none
+            Instruction::Slice => {
+                let sequence = self.pop_value()?;
+                let step = self.pop_value()?;
+                let end = self.pop_value()?;
+                let start = self.pop_value()?;
+                self.push_value(sequence.slice(start, end, step))?;
             },
 
             // == the topmost objects
             Instruction::Equal => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                if a == b {
-                    self.push_value(num("1"));
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                if let Some(problem) = first_problem(&a, &b) {
+                    self.push_value(problem)?;
+                } else if a.get_type() == Type::Num && b.get_type() == Type::Num {
+                    // NaN is not equal to itself here, unlike in rust
+                    let order = a.as_number().numeric_cmp(&b.as_number());
+                    self.push_value(boolean(order == Some(Ordering::Equal)))?;
                 } else {
-                    self.push_value(num("0"));
+                    self.push_value(boolean(a == b))?;
                 }
             },
 
             // > the topmost objects
             Instruction::Greater => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                if a.as_number() > b.as_number() {
-                    self.push_value(num("1"));
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                if let Some(problem) = ordering_problem(&a, &b) {
+                    self.push_value(problem)?;
                 } else {
-                    self.push_value(num("0"));
+                    let order = a.as_number().numeric_cmp(&b.as_number());
+                    self.push_value(boolean(order == Some(Ordering::Greater)))?;
                 }
             },
             
             // < the topmost objects
             Instruction::Less => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                if a.as_number() < b.as_number() {
-                    self.push_value(num("1"));
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                if let Some(problem) = ordering_problem(&a, &b) {
+                    self.push_value(problem)?;
                 } else {
-                    self.push_value(num("0"));
+                    let order = a.as_number().numeric_cmp(&b.as_number());
+                    self.push_value(boolean(order == Some(Ordering::Less)))?;
                 }
             },
             
 
             // not the topmost object
             Instruction::Not => {
-                let a = self.pop_value();
-                self.push_value(!a);
+                let a = self.pop_value()?;
+                self.push_value(!a)?;
             },
 
             // add the topmost objects
             Instruction::Add => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                self.push_value(a + b);
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                if a.get_type() == Type::Str && b.get_type() == Type::Str && self.limits.max_value_size.is_some() {
+                    self.check_size(a.size() + b.size())?;
+                }
+                self.push_value(a + b)?;
             },
 
             // multiply the topmost objects
             Instruction::Mul => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                self.push_value(a * b);
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(a * b)?;
             },
             
             // subtract the topmost objects
             Instruction::Sub => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                self.push_value(a - b);
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(a - b)?;
             },
             
             // divide the topmost objects
             Instruction::Div => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                self.push_value(a / b);
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(a / b)?;
             },
 
             // apply the % operator to the topmost objects
             Instruction::Mod => {
-                let a = self.pop_value();
-                let b = self.pop_value();
-                self.push_value(a % b);
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(a % b)?;
             },
             
             // call the topmost object on the stack as a function
             Instruction::Call => {
-                let f = self.pop();
-                self.call(f);
+                let f = self.pop()?;
+                self.call_any(f)?;
             },
 
             // load a variable with a given name
             Instruction::Load => {
-                let name = self.pop_value().as_string();
-                let value = self.load(name);
-                self.push(value);
+                let name = self.pop_value()?.as_string();
+                let value = self.load(name)?;
+                self.push(value)?;
             },
 
             // store takes a name and a value
             // and stores the value under that name
             // as a variable that can be loaded
             Instruction::Store => {
-                let name = self.pop_value().as_string();
-                let value = self.pop();
+                let name = self.pop_value()?.as_string();
+                let value = self.pop()?;
                 self.store(name, value);
             },
 
@@ -260,16 +686,13 @@ impl StackFrame {
                 // println!("contents {:?}", self.contents.len());
                 loop {
 
-                    if !self.contents.iter().any(|v| v.first.get_type() == Type::Instance) {
-                        throw("No instance to set attribute of", self.contents.clone());
+                    if !self.current().iter().any(|v| v.first.get_type() == Type::Instance) {
+                        return Err(self.error(ErrorKind::MissingInstance, "No instance to get attribute of"));
                     }
 
-                    let back = match self.contents.last() {
+                    let back = match self.current().last() {
                         Some(k) => k.first.clone(),
-                        None => {
-                            throw("Could not get back item from stack", self.contents.clone());
-                            Value::from_nothing()
-                        }
+                        None => return Err(self.error(ErrorKind::StackUnderflow, "Could not get back item from stack"))
                     };
 
                     if back.get_type() == Type::Instance {
@@ -277,21 +700,23 @@ impl StackFrame {
                     }
 
                     names.push(
-                        self.pop_value().as_string()
+                        self.pop_value()?.as_string()
                         );
                     
-                    if self.contents.len() < 1 {
-                        throw("Too few items on stack to set attribute", self.contents.clone());
+                    if self.current().is_empty() {
+                        return Err(self.error(ErrorKind::StackUnderflow, "Too few items on stack to get attribute"));
                     }
                 }
-                if names.len() < 1 {
-                    throw("Could not set attribute of object without the attribute name", self.contents.clone());
+                if names.is_empty() {
+                    return Err(self.error(ErrorKind::MissingAttribute, "Could not get attribute of object without the attribute name"));
                 }
                 names.reverse();
 
-                let mut object = self.pop_value();
+                let mut object = self.pop_value()?;
+                let attr = object.get_attr_recursive(names)
+                    .map_err(|e| e.with_stack(self.contents()))?;
 
-                self.push_value(object.get_attr_recursive(names));
+                self.push_value(attr)?;
             },
 
             // setattr modifies an attribute of an object
@@ -300,16 +725,13 @@ impl StackFrame {
                 let mut names: Vec<String> = vec![];
                 // println!("contents {:?}", self.contents.len());
                 loop {
-                    if !self.contents.iter().any(|v| v.first.get_type() == Type::Instance) {
-                        throw("No instance to set attribute of", self.contents.clone());
+                    if !self.current().iter().any(|v| v.first.get_type() == Type::Instance) {
+                        return Err(self.error(ErrorKind::MissingInstance, "No instance to set attribute of"));
                     }
 
-                    let back = match self.contents.last() {
+                    let back = match self.current().last() {
                         Some(k) => k.first.clone(),
-                        None => {
-                            throw("Could not get back item from stack", self.contents.clone());
-                            Value::from_nothing()
-                        }
+                        None => return Err(self.error(ErrorKind::StackUnderflow, "Could not get back item from stack"))
                     };
 
                     if back.get_type() == Type::Instance {
@@ -317,198 +739,755 @@ impl StackFrame {
                     }
 
                     names.push(
-                        self.pop_value().as_string()
+                        self.pop_value()?.as_string()
                         );
                     
-                    if self.contents.len() < 2 {
-                        throw("Too few items on stack to set attribute", self.contents.clone());
+                    if self.current().len() < 2 {
+                        return Err(self.error(ErrorKind::StackUnderflow, "Too few items on stack to set attribute"));
                     }
                 }
-                if names.len() < 1 {
-                    throw("Could not set attribute of object without the attribute name", self.contents.clone());
+                if names.is_empty() {
+                    return Err(self.error(ErrorKind::MissingAttribute, "Could not set attribute of object without the attribute name"));
                 }
 
                 names.reverse();
 
-                let mut object = self.pop_value();
-                let data = self.pop_value();
+                let mut object = self.pop_value()?;
+                let data = self.pop_value()?;
 
+                let result = object.set_attr_recursive(names, data)
+                    .map_err(|e| e.with_stack(self.contents()))?;
 
-                self.push_value(object.set_attr_recursive(names, data));
+                self.push_value(result)?;
             },
 
             // execute takes the topmost object on the stack
             // and executes it as a foreign function
             // a foreign function takes a Value and returns
-            // a Value
+            // a Value, or an error that stops the program
             Instruction::Execute => {
-                let mut foreign_function = self.pop_value();
-                let argument = self.pop_value();
-                self.push_value(
-                    foreign_function.call_foreign_function(argument)
-                    );
+                let mut foreign_function = self.pop_value()?;
+                let argument = self.pop_value()?;
+                let result = foreign_function.call_foreign_function(argument)
+                    .map_err(|e| e.with_stack(self.contents()))?;
+                self.push_value(result)?;
             },
 
             // pass does nothing
-            Instruction::Pass => self.push_value(instruction)
+            Instruction::Pass => self.push_value(instruction)?,
+
+            // is the topmost object a problem?
+            Instruction::IsProblem => {
+                let a = self.pop_value()?;
+                self.push_value(boolean(a.is_problem()))?;
+            },
+
+            // the kind of the topmost problem, as a string
+            Instruction::ProblemKind => {
+                let a = self.pop_value()?;
+                let kind = match a.get_type() {
+                    Type::Problem(p) => string(&format!("{:?}", p)),
+                    _ => not_a_problem(&a)
+                };
+                self.push_value(kind)?;
+            },
+
+            // the message of the topmost problem
+            Instruction::ProblemMessage => {
+                let a = self.pop_value()?;
+                let message = match a.problem_message() {
+                    Some(m) => string(m),
+                    None => not_a_problem(&a)
+                };
+                self.push_value(message)?;
+            },
+
+            // the payload of the topmost problem, or none if it has none
+            Instruction::ProblemPayload => {
+                let a = self.pop_value()?;
+                let payload = match a.problem_payload() {
+                    Some(p) => p,
+                    None if a.is_problem() => none(),
+                    None => not_a_problem(&a)
+                };
+                self.push_value(payload)?;
+            },
+
+            // raise the topmost object, stopping the program
+            // unless a `try` catches it
+            Instruction::Raise => {
+                let value = self.pop_value()?;
+                return Err(RuntimeError::raise(value).with_stack(self.contents()));
+            },
+
+            // call a body with a handler. if the body raises a value or
+            // fails with a runtime error, the values it pushed are thrown
+            // away and the handler is called with what was raised
+            Instruction::Try => {
+                let handler = self.pop()?;
+                let body = self.pop()?;
+                // the body can use up values the caller pushed before it,
+                // so the whole stack is kept for the handler
+                let contents = self.contents.clone();
+                let bases = self.frames.iter().map(|frame| frame.base).collect();
+                self.tasks.push(Task::Catch {handler, contents, bases});
+                self.call(body)?;
+            },
+
+            // push an empty map
+            Instruction::NewMap => self.push_value(Value::from_map(Map::new()))?,
+
+            // set a key of the topmost map, taking the key
+            // and then the value from below the map
+            Instruction::MapInsert => {
+                let mut map = self.pop_value()?;
+                let key = self.pop_value()?;
+                let value = self.pop_value()?;
+                let result = match map_key(&map, &key) {
+                    Ok(k) => {
+                        if self.limits.max_value_size.is_some() {
+                            // the entry this replaces no longer counts
+                            let replaced = map.get_map().get(&k).map_or(0, |old| 1 + old.size());
+                            self.check_size(map.size() + 1 + value.size() - replaced)?;
+                        }
+                        map.get_map_mut().insert(k, value);
+                        map
+                    },
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // the value of a key in the topmost map,
+            // or a KeyError problem if it isn't there
+            Instruction::MapGet => {
+                let map = self.pop_value()?;
+                let key = self.pop_value()?;
+                let result = match map_key(&map, &key) {
+                    Ok(k) => match map.get_map().get(&k) {
+                        Some(value) => value.clone(),
+                        None => Value::from_problem_with(
+                            Problem::KeyError,
+                            &format!("no key {} in map", render(&key)),
+                            Some(key)
+                        )
+                    },
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // the topmost map without a key, which doesn't need to be there
+            Instruction::MapRemove => {
+                let mut map = self.pop_value()?;
+                let key = self.pop_value()?;
+                let result = match map_key(&map, &key) {
+                    Ok(k) => {
+                        if map.get_map().contains_key(&k) {
+                            map.get_map_mut().remove(&k);
+                        }
+                        map
+                    },
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // does the topmost map have a key?
+            Instruction::MapContains => {
+                let map = self.pop_value()?;
+                let key = self.pop_value()?;
+                let result = match map_key(&map, &key) {
+                    Ok(k) => boolean(map.get_map().contains_key(&k)),
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // a list of the keys of the topmost map, in order
+            Instruction::MapKeys => {
+                let map = self.pop_value()?;
+                let result = match expect_map(&map) {
+                    Ok(()) => Value::from_vector(map.get_map().keys().map(Key::to_object).collect()),
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // a list of the values of the topmost map, in the order of their keys
+            Instruction::MapValues => {
+                let map = self.pop_value()?;
+                let result = match expect_map(&map) {
+                    Ok(()) => Value::from_vector(map.get_map().values().cloned().collect()),
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // the number of keys in the topmost map
+            Instruction::MapLength => {
+                let map = self.pop_value()?;
+                let result = match expect_map(&map) {
+                    Ok(()) => Value::from_number((map.get_map().len() as i64).into()),
+                    Err(problem) => problem
+                };
+                self.push_value(result)?;
+            },
+
+            // logical operators on the truthiness of the topmost objects
+            Instruction::And | Instruction::Or | Instruction::Xor => {
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                let result = match first_problem(&a, &b) {
+                    Some(problem) => problem,
+                    None => boolean(match instruction.as_instruction() {
+                        Instruction::And => a.is_truthy() && b.is_truthy(),
+                        Instruction::Or => a.is_truthy() || b.is_truthy(),
+                        _ => a.is_truthy() != b.is_truthy()
+                    })
+                };
+                self.push_value(result)?;
+            },
+
+            // the math instructions on numbers
+            Instruction::Abs | Instruction::Neg | Instruction::Sqrt |
+            Instruction::Exp | Instruction::Ln | Instruction::Log10 |
+            Instruction::Sin | Instruction::Cos | Instruction::Tan |
+            Instruction::Asin | Instruction::Acos | Instruction::Atan => {
+                let a = self.pop_value()?;
+                self.push_value(math::unary(instruction.as_instruction(), a))?;
+            },
+
+            Instruction::Pow | Instruction::IntDiv | Instruction::Min | Instruction::Max |
+            Instruction::BitAnd | Instruction::BitOr | Instruction::BitXor => {
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(math::binary(instruction.as_instruction(), a, b))?;
+            },
+
+            // round the top number to the number of places below it
+            Instruction::Floor | Instruction::Ceil | Instruction::Round | Instruction::Trunc => {
+                let a = self.pop_value()?;
+                let places = self.pop_value()?;
+                self.push_value(math::rounding(instruction.as_instruction(), a, places))?;
+            },
+
+            // shift the top integer by the number of bits below it
+            Instruction::Shl | Instruction::Shr => {
+                let a = self.pop_value()?;
+                let bits = self.pop_value()?;
+                self.push_value(math::shift(instruction.as_instruction(), a, bits))?;
+            },
+
+            // is the top value NaN, or an infinity?
+            Instruction::IsNan | Instruction::IsInfinite => {
+                let a = self.pop_value()?;
+                self.push_value(math::query(instruction.as_instruction(), a))?;
+            },
+
+            // write the top number with the format string below it
+            Instruction::FormatNum => {
+                let a = self.pop_value()?;
+                let format = self.pop_value()?;
+                let result = format::format_number(a, format);
+                if self.limits.max_value_size.is_some() {
+                    self.check_size(result.size())?;
+                }
+                self.push_value(result)?;
+            },
+
+            // write the top integer in the radix below it
+            Instruction::ToRadix => {
+                let a = self.pop_value()?;
+                let radix = self.pop_value()?;
+                let result = format::to_radix(a, radix);
+                if self.limits.max_value_size.is_some() {
+                    self.check_size(result.size())?;
+                }
+                self.push_value(result)?;
+            },
+
+            Instruction::ParseNum => {
+                let a = self.pop_value()?;
+                self.push_value(format::parse_number(a))?;
+            },
+
+            // `and` and `or` that only call the function on top of the
+            // stack when the value below it doesn't decide the result
+            Instruction::AndThen | Instruction::OrElse => {
+                let right = self.pop()?;
+                let left = self.pop_value()?;
+                let decides = match instruction.as_instruction() {
+                    Instruction::AndThen => !left.is_truthy(),
+                    _ => left.is_truthy()
+                };
+
+                if left.is_problem() {
+                    self.push_value(left)?;
+                } else if decides {
+                    self.push_value(boolean(left.is_truthy()))?;
+                } else {
+                    self.tasks.push(Task::Decide);
+                    if right.first.get_type() == Type::Function {
+                        self.call_any(right)?;
+                    } else {
+                        self.push(right)?;
+                    }
+                }
+            }
         }
+        Ok(())
     }
 
-    // is the stack empty?
-    fn is_empty(&self) -> bool {
-        self.contents.len() == 0
+    // the call being run
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("the outermost call is never removed")
     }
 
-    // does this stack frame have a valid outer stack?
-    fn has_outer_stack(&self) -> bool {
-        match self.outer_stack {
-            Some(_) => true,
-            None => false
-        }
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("the outermost call is never removed")
     }
 
-    fn while_function(&mut self, condition: Value, body: Value) {
-        loop {
-            for instruction in condition.as_list() {
-                self.step(instruction)
-            }
+    // the values the current call has pushed and not yet popped
+    fn current(&self) -> &[Pair<Value, Scope>] {
+        &self.contents[self.frame().base..]
+    }
 
-            let result = self.pop_value(); 
-            if result == num("0") || result == none() {
-                break;
+    // use up one unit of fuel, failing if there is none left
+    // or if the deadline has passed
+    fn consume_fuel(&mut self) -> Result<(), RuntimeError> {
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                return Err(self.error(ErrorKind::OutOfFuel, "Ran out of fuel"));
             }
+            self.fuel = Some(fuel - 1);
+        }
 
-            for instruction in body.as_list() {
-                self.step(instruction)
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(self.error(ErrorKind::OutOfFuel, "Ran past the deadline"));
             }
         }
+        Ok(())
+    }
+
+    // create an error carrying a snapshot of this stack
+    fn error(&self, kind: ErrorKind, message: &str) -> RuntimeError {
+        RuntimeError::new(kind, message).with_stack(self.contents())
     }
 
     // this function calls the topmost object on the stack as function
-    fn call(&mut self, object_and_scope: Pair<Value, Scope>) {
-        // create a new stackframe
-        let mut s = StackFrame::new(
-            Some(Box::new(self.clone())),
-            object_and_scope.second,
-            object_and_scope.first
-        );
-
-        // run the stackframe
-        s.run();
-
-        // pop off all of the arguments given to the function called
-        for _ in 0..s.number_of_args_taken {
-            // println!("popped arg {:?}", self.pop().first);
-            self.pop();
+    fn call(&mut self, object_and_scope: Pair<Value, Scope>) -> Result<(), RuntimeError> {
+        if let Some(max) = self.limits.max_call_depth {
+            if self.depth() >= max {
+                return Err(self.error(
+                    ErrorKind::CallDepthExceeded,
+                    &format!("Calls nested past the limit of {} frames", max)
+                ));
+            }
         }
 
-        // receive returned values
-        s.contents.reverse();
-        while !s.is_empty() {
-            self.push(s.pop());
+        // the called function starts with no values of its own,
+        // and whatever it leaves on the stack is what it returns.
+        // it runs once the instruction that called it has finished
+        let base = self.contents.len();
+        self.frames.push(Frame {scope: object_and_scope.second, base});
+        self.tasks.push(Task::Return);
+        self.tasks.push(Task::Run {instructions: object_and_scope.first, index: 0, annotate: true});
+        Ok(())
+    }
+
+    // call a function, whether it is a foreign one or not
+    fn call_any(&mut self, function: Pair<Value, Scope>) -> Result<(), RuntimeError> {
+        if function.first.is_foreign_function() {
+            self.call_foreign(function.first)
+        } else {
+            self.call(function)
         }
     }
 
-    // retrieve the value with a given variable name
-    fn load(&mut self, name: String) -> Pair<Value, Scope> {
-        let result = self.scope.get(name.clone());
-        let empty = Pair{first: Value::from_nothing(), second: Scope::new(None)};
-        if result.first == empty.first && result.second == empty.second {
-            return match &mut self.outer_stack {
-                Some(s) => {
-                    s.load(name.clone())
-                },
-                None => {
-                    let v = self.pop();
-                    self.store(name.clone(), v);
-                    self.load(name.clone())
+    // call a foreign function, giving a native function
+    // its declared number of arguments as a list
+    fn call_foreign(&mut self, function: Value) -> Result<(), RuntimeError> {
+        let foreign_function = function.get_foreign_function();
+        let argument = match foreign_function.arity() {
+            Some(arity) if self.contents.len() < arity => {
+                // not enough values for the function to be called with
+                return self.push_value(arity_problem(arity, self.contents.len()));
+            },
+            Some(arity) => {
+                let mut arguments = vec![];
+                for _ in 0..arity {
+                    arguments.push(self.pop_value()?);
                 }
-            };
-        } else {
-            return result;
+                arguments.reverse();
+                list(&arguments)
+            },
+            None => self.pop_value()?
+        };
+
+        let result = foreign_function.call(argument)
+            .map_err(|e| e.with_stack(self.contents()))?;
+        self.push_value(result)
+    }
+
+    // retrieve the value with a given variable name,
+    // or the native function with that name
+    fn load(&mut self, name: String) -> Result<Pair<Value, Scope>, RuntimeError> {
+        for frame in self.frames.iter().rev() {
+            // a variable that is none without a scope counts as undefined
+            match frame.scope.get(&name) {
+                Some(result) if result.first.get_type() != Type::Nothing || !result.second.is_empty() => {
+                    return Ok(result);
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(function) = self.registry.get(&name) {
+            return Ok(Pair{first: function, second: Scope::new(None)});
+        }
+
+        if self.frames.len() == 1 {
+            // the outermost call defines an undefined variable
+            // as the topmost value on its stack
+            let v = self.pop()?;
+            self.store(name.clone(), v);
+            return self.load(name);
+        }
+
+        // called functions only see a copy of the outermost call's
+        // topmost value, which stays on the stack and is never defined
+        let base = self.frames[1].base;
+        if base == 0 {
+            return Err(self.error(ErrorKind::StackUnderflow, "Could not pop off of stack"));
+        }
+        let object = self.contents[base - 1].clone();
+        if object.first.get_type() == Type::Function && !object.second.is(&self.frames[0].scope) {
+            return Ok(object);
         }
+        Ok(Pair{first: object.first, second: Scope::new(None)})
     }
 
     // store a value under the given variable name
     fn store(&mut self, name: String, object: Pair<Value, Scope>) {
-        if object.second != self.scope {
-            self.scope.define(name, object);
+        if !object.second.is(self.scope()) {
+            self.frame_mut().scope.define(name, object);
         } else {
-            self.scope.define(name, Pair{first: object.first, second: Scope::new(None)});
+            self.frame_mut().scope.define(name, Pair{first: object.first, second: Scope::new(None)});
         }
-        // match object.first.get_type() {
-        //     Type::Function => self.scope.define(name, object),
-        //     _ => self.scope.define(name, Pair{first: object.first, second: Scope::new(None)}),
-        // }
     }
 
     // push an object with its saved scope onto the stack
-    fn push(&mut self, object_and_scope: Pair<Value, Scope>) {
-        // self.number_of_args_taken += 1;
+    fn push(&mut self, object_and_scope: Pair<Value, Scope>) -> Result<(), RuntimeError> {
+        if let Some(max) = self.limits.max_stack_length {
+            if self.contents.len() >= max {
+                return Err(self.error(
+                    ErrorKind::StackOverflow,
+                    &format!("Stack grew past the limit of {} values", max)
+                ));
+            }
+        }
         self.contents.push(object_and_scope);
+        Ok(())
     }
 
     // push an object without a scope onto the stack (used for literals)
-    fn push_value(&mut self, object: Value) {
-        // self.number_of_args_taken += 1;
-        self.contents.push(Pair {
+    fn push_value(&mut self, object: Value) -> Result<(), RuntimeError> {
+        let scope = self.scope().clone();
+        self.push(Pair {
             first: object,
-            second: self.scope.clone()
-        });
+            second: scope
+        })
     }
 
-    // pop an object with its scope off of the stack or the parent stackframes' stack
-    fn pop(&mut self) -> Pair<Value, Scope> {
-        if self.is_empty() && self.has_outer_stack() {
-            match &mut self.outer_stack {
-                Some(s) => {
-                    self.number_of_args_taken += 1;
-                    s.pop()
-                },
-                None => {
-                    throw("Could not pop off of stack", self.contents.clone());
-                    return Pair{first: Value::from_nothing(), second: Scope::new(None)}
-                }
+    // push an object onto a list, as long as the list stays small enough
+    fn list_push(&self, list: &mut Value, object: Value) -> Result<(), RuntimeError> {
+        if self.limits.max_value_size.is_some() {
+            self.check_size(list.size() + object.size())?;
+        }
+        list.list_push(object);
+        Ok(())
+    }
+
+    // fail if a value of this size would be larger than allowed
+    fn check_size(&self, size: usize) -> Result<(), RuntimeError> {
+        if let Some(max) = self.limits.max_value_size {
+            if size > max {
+                return Err(self.error(
+                    ErrorKind::ValueTooLarge,
+                    &format!("Value grew past the limit of {} cells", max)
+                ));
             }
-        } else {
+        }
+        Ok(())
+    }
 
-            let back: Pair<Value, Scope> = match self.contents.last() {
-                Some(v) => v.clone(),
-                None => {
-                    throw("Could not pop off of stack", self.contents.clone());
-                    Pair{first: Value::from_nothing(), second: Scope::new(None)}
+    // pop an object with its scope off of the stack, taking it
+    // from the callers' values once the current call has none left
+    fn pop(&mut self) -> Result<Pair<Value, Scope>, RuntimeError> {
+        match self.contents.pop() {
+            Some(v) => {
+                // every call that owned the value has used up one argument
+                let length = self.contents.len();
+                for frame in self.frames.iter_mut().rev() {
+                    if frame.base <= length {
+                        break;
+                    }
+                    frame.base = length;
                 }
-            };
-            
-            self.contents.pop();
-            back.clone()
+                Ok(v)
+            },
+            None => Err(self.error(ErrorKind::StackUnderflow, "Could not pop off of stack"))
         }
     }
 
     // pop an object (a literal) off of the stack or the parent stackframes' stack
-    fn pop_value(&mut self) -> Value {
-        if self.is_empty() && self.has_outer_stack() {
-            match &mut self.outer_stack {
-                Some(s) => {
-                    self.number_of_args_taken += 1;
-                    s.pop().first
-                },
-                None => {
-                    throw("Could not pop off of stack", self.contents.clone());
-                    return Value::from_nothing();
-                }
-            }
-        } else {
-            let back: Value = match self.contents.last() {
-                Some(v) => v.first.clone(),
-                None => {
-                    throw("Could not pop off of stack", self.contents.clone());
-                    Value::from_nothing()
-                }
-            };
-            
-            self.contents.pop();
-            back.clone()
-        }
+    fn pop_value(&mut self) -> Result<Value, RuntimeError> {
+        Ok(self.pop()?.first)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn frame(source: &str) -> StackFrame {
+        StackFrame::from_instructions(parse(source).expect("test programs parse"))
+    }
+
+    // run a program, returning what it left on the stack
+    fn run(source: &str) -> Result<Vec<String>, RuntimeError> {
+        let mut frame = frame(source);
+        frame.run()?;
+        Ok(frame.contents().iter().map(render).collect())
+    }
+
+    #[test]
+    fn run_returns_the_stack() {
+        assert_eq!(run("1 2 add").unwrap(), ["3"]);
+    }
+
+    #[test]
+    fn underflow_is_an_error_instead_of_an_exit() {
+        let error = run("1 add").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StackUnderflow);
+        assert_eq!(error.instruction(), Some(1));
+    }
+
+    #[test]
+    fn errors_keep_the_innermost_instruction() {
+        let error = run("1 { pass add add } call").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StackUnderflow);
+        assert_eq!(error.instruction(), Some(2));
+    }
+
+    #[test]
+    fn errors_carry_the_stack() {
+        let error = run("\"a\" <> setattr").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MissingAttribute);
+        let stack: Vec<String> = error.stack().iter().map(render).collect();
+        assert_eq!(stack, ["\"a\"", "<>"]);
+    }
+
+    #[test]
+    fn endless_recursion_is_an_error() {
+        let error = run("{ \"f\" load call } \"f\" store \"f\" load call").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CallDepthExceeded);
+    }
+
+    #[test]
+    fn deep_recursion_does_not_use_the_native_stack() {
+        // counts down from n, calling itself once per level
+        let countdown = "{ \"n\" store { } { 1 \"n\" load sub \"f\" load call } 0 \"n\" load greater if call } \"f\" store";
+        let mut frame = frame(&format!("{} 500 \"f\" load call", countdown));
+        frame.set_limits(Limits {max_call_depth: Some(2000), ..Limits::default()});
+        frame.run().unwrap();
+        assert_eq!(frame.depth(), 0);
+    }
+
+    #[test]
+    fn the_call_depth_limit_can_be_changed() {
+        let mut frame = frame("{ { { } call } call } call");
+        frame.set_limits(Limits {max_call_depth: Some(2), ..Limits::default()});
+        let error = frame.run().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CallDepthExceeded);
+        assert_eq!(frame.depth(), 0);
+    }
+
+    #[test]
+    fn the_stack_length_can_be_limited() {
+        let mut frame = frame("1 2 3 4");
+        frame.set_limits(Limits {max_stack_length: Some(3), ..Limits::default()});
+        let error = frame.run().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StackOverflow);
+        assert_eq!(error.instruction(), Some(3));
+    }
+
+    #[test]
+    fn the_value_size_can_be_limited() {
+        let mut lists = frame("[ ] \"l\" store { 1 \"l\" load append \"l\" store } { true } while");
+        lists.set_limits(Limits {max_value_size: Some(100), ..Limits::default()});
+        assert_eq!(lists.run().unwrap_err().kind(), ErrorKind::ValueTooLarge);
+
+        let mut maps = frame("newmap \"m\" store 0 \"i\" store \
+            { \"i\" load \"i\" load \"m\" load mapinsert \"m\" store 1 \"i\" load add \"i\" store } { true } while");
+        maps.set_limits(Limits {max_value_size: Some(100), ..Limits::default()});
+        assert_eq!(maps.run().unwrap_err().kind(), ErrorKind::ValueTooLarge);
+    }
+
+    #[test]
+    fn replacing_a_map_entry_only_counts_the_new_value() {
+        let mut map = Map::new();
+        map.insert(Key::Str("k".to_string()), num("1"));
+        let limits = Limits {max_value_size: Some(Value::from_map(map).size()), ..Limits::default()};
+
+        let insert = |key: &str, n: i64| format!("{} \"{}\" \"m\" load mapinsert \"m\" store ", n, key);
+        let mut replaced = frame(&format!("newmap \"m\" store {}{}{}", insert("k", 1), insert("k", 2), insert("k", 3)));
+        replaced.set_limits(limits);
+        replaced.run().unwrap();
+
+        let mut added = frame(&format!("newmap \"m\" store {}{}", insert("k", 1), insert("j", 2)));
+        added.set_limits(limits);
+        assert_eq!(added.run().unwrap_err().kind(), ErrorKind::ValueTooLarge);
+    }
+
+    #[test]
+    fn comparisons_pass_problems_through() {
+        assert_eq!(run("1 \"a\" add 1 equal isproblem").unwrap(), ["true"]);
+        assert_eq!(run("1 1 \"a\" add equal isproblem").unwrap(), ["true"]);
+        assert_eq!(run("1 \"a\" add 1 greater isproblem").unwrap(), ["true"]);
+    }
+
+    #[test]
+    fn only_numbers_have_a_size_to_compare() {
+        assert_eq!(run("\"b\" \"a\" greater problemkind").unwrap(), ["\"TypeError\""]);
+        assert_eq!(run("1 [ ] less problemkind").unwrap(), ["\"TypeError\""]);
+        assert_eq!(run("none 1 less problemmessage").unwrap(), ["\"cannot compare the size of Nothing values\""]);
+        assert_eq!(run("1 2 less").unwrap(), ["false"]);
+    }
+
+    #[test]
+    fn try_puts_the_stack_back_for_the_handler() {
+        assert_eq!(run("1 { 2 3 \"x\" raise } { } try").unwrap(), ["1", "\"x\""]);
+        assert_eq!(run("1 2 { add 5 raise } { } try").unwrap(), ["1", "2", "5"]);
+        assert_eq!(run("1 2 { add add } { } try isproblem").unwrap(), ["1", "2", "true"]);
+    }
+
+    #[test]
+    fn index_and_slice_take_their_arguments_in_order() {
+        assert_eq!(run("-1 \"abc\" index").unwrap(), ["\"c\""]);
+        assert_eq!(run("1 none none \"abc\" slice").unwrap(), ["\"bc\""]);
+        assert_eq!(run("none none -1 \"abc\" slice").unwrap(), ["\"cba\""]);
+    }
+
+    #[test]
+    fn comparisons_and_logic_push_booleans() {
+        assert_eq!(run("1 1 equal 2 1 greater 2 1 less").unwrap(), ["true", "false", "true"]);
+        assert_eq!(run("1 \"\" and 1 \"\" or 1 2 xor").unwrap(), ["false", "true", "false"]);
+        assert_eq!(run("none not").unwrap(), ["true"]);
+        assert_eq!(run("{ 0 } { 1 } [ ] if call").unwrap(), ["0"]);
+    }
+
+    #[test]
+    fn andthen_and_orelse_only_call_what_they_need() {
+        assert_eq!(run("0 { 1 \"a\" add } andthen").unwrap(), ["false"]);
+        assert_eq!(run("1 { 1 \"a\" add } orelse").unwrap(), ["true"]);
+        assert_eq!(run("1 { \"x\" } andthen 0 { [ ] } orelse").unwrap(), ["true", "false"]);
+        assert_eq!(run("1 \"a\" add { 1 } orelse isproblem").unwrap(), ["true"]);
+    }
+
+    #[test]
+    fn math_instructions_take_the_top_value_first() {
+        assert_eq!(run("2 10 pow 2 3.14159 round -2 1234 floor").unwrap(), ["100", "3.14", "1200"]);
+    }
+
+    #[test]
+    fn nan_is_not_equal_to_anything() {
+        let nan = "-1 sqrt";
+        let source = format!("{0} {0} equal {0} 1 greater {0} 1 less 1 1.0 equal", nan);
+        assert_eq!(run(&source).unwrap(), ["false", "false", "false", "true"]);
+    }
+
+    #[test]
+    fn numbers_convert_to_and_from_strings() {
+        let source = "\",.2\" 1234.5 formatnum 16 255 toradix \" -12 \" parsenum";
+        assert_eq!(run(source).unwrap(), ["\"1,234.50\"", "\"ff\"", "-12"]);
+    }
+
+    #[test]
+    fn execute_keeps_the_stack_and_variables() {
+        let mut frame = frame("");
+        frame.execute(parse("1 \"x\" store 5").unwrap()).unwrap();
+        frame.execute(parse("\"x\" load add").unwrap()).unwrap();
+        assert_eq!(frame.contents().iter().map(render).collect::<Vec<_>>(), ["6"]);
+        assert_eq!(frame.scope().variables().len(), 1);
+    }
+
+    #[test]
+    fn execute_can_continue_after_an_error() {
+        let mut frame = frame("");
+        assert!(frame.execute(parse("1 add").unwrap()).is_err());
+        frame.execute(parse("2 3 add").unwrap()).unwrap();
+        assert_eq!(frame.contents().iter().map(render).collect::<Vec<_>>(), ["5"]);
+    }
+
+    #[test]
+    fn fuel_counts_instructions() {
+        let mut fed = frame("1 2 add");
+        fed.set_fuel(Some(3));
+        fed.run().unwrap();
+        assert_eq!(fed.fuel(), Some(0));
+        assert_eq!(fed.steps(), 3);
+
+        let mut starved = frame("1 2 add");
+        starved.set_fuel(Some(2));
+        let error = starved.run().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::OutOfFuel);
+        assert_eq!(error.instruction(), Some(2));
+    }
+
+    #[test]
+    fn fuel_stops_empty_loops() {
+        let mut frame = frame("{ } { true } while");
+        frame.set_fuel(Some(1000));
+        assert_eq!(frame.run().unwrap_err().kind(), ErrorKind::OutOfFuel);
+    }
+
+    #[test]
+    fn running_out_of_fuel_cannot_be_caught() {
+        let mut frame = frame("{ { } { true } while } { } try");
+        frame.set_fuel(Some(1000));
+        assert_eq!(frame.run().unwrap_err().kind(), ErrorKind::OutOfFuel);
+    }
+
+    #[test]
+    fn the_deadline_stops_endless_loops() {
+        let mut frame = frame("{ } { true } while");
+        frame.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(20)));
+        assert_eq!(frame.run().unwrap_err().kind(), ErrorKind::OutOfFuel);
+    }
+
+    #[test]
+    fn calls_take_arguments_from_the_callers_stack() {
+        assert_eq!(run("1 2 { add } call 3").unwrap(), ["3", "3"]);
+    }
+
+    #[test]
+    fn recursive_calls_return_their_results() {
+        let fib = "{ \"n\" store \
+                   { 1 \"n\" load sub \"fib\" load call 2 \"n\" load sub \"fib\" load call add } \
+                   { \"n\" load } \
+                   2 \"n\" load less if call } \"fib\" store";
+        assert_eq!(run(&format!("{} 15 \"fib\" load call", fib)).unwrap(), ["610"]);
+    }
+
+    #[test]
+    fn functions_keep_the_scope_they_were_made_in() {
+        let program = "{ 5 \"x\" store { \"x\" load } } call \"get\" store \
+                       \"get\" load call";
+        assert_eq!(run(program).unwrap(), ["5"]);
     }
 }
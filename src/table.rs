@@ -9,7 +9,7 @@ pub struct Table<T> {
 
 impl <T: Clone> Table<T> {
     pub fn new() -> Self {
-        return Self{contents: HashMap::new()}
+        Self{contents: HashMap::new()}
     }
 
     pub fn set(&mut self, name: String, value: T) {
@@ -20,11 +20,38 @@ impl <T: Clone> Table<T> {
         self.contents.get(&name).cloned()
     }
 
+    // borrow a value without copying it
+    pub fn get_ref(&self, name: &str) -> Option<&T> {
+        self.contents.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.contents.get_mut(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.contents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contents.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&String, &T)> {
+        self.contents.iter()
+    }
+
     pub fn keys(&self) -> Vec<String> {
-        self.contents.iter().map(|(key, _)| key.clone()).collect()
+        self.contents.keys().cloned().collect()
     }
 
     pub fn values(&self) -> Vec<T> {
-        self.contents.iter().map(|(_, value)| value.clone()).collect()
+        self.contents.values().cloned().collect()
+    }
+}
+
+impl <T: Clone> Default for Table<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
\ No newline at end of file
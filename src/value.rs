@@ -3,18 +3,37 @@ use std::fmt::{Display};
 use std::str::FromStr;
 use std::cmp::Ordering;
 use std::ops::{Add, Sub, Mul, Div, Rem, Not};
+use std::rc::Rc;
+use std::cell::Cell;
 
 use crate::table::*;
+use crate::map::Map;
 use crate::object::*;
-use crate::literals::*;
+use crate::number::Number;
+use crate::bytecode;
+use crate::error::BytecodeError;
 
-#[derive(Debug, Clone, PartialEq)]
+// cloning a value only copies pointers to its contents, list and
+// attributes, which are copied for real the first time one of the
+// values sharing them is changed
+#[derive(Debug, Clone)]
 pub struct Value {
     value_type: Type,
-    function: fn(Self) -> Self,
-    contents: Contents,
-    list: Vec<Self>,
-    attributes: Table<Self>,
+    function: Option<ForeignFunction<Self>>,
+    contents: Rc<Contents>,
+    string: Rc<str>,
+    list: Rc<Vec<Self>>,
+    list_size: Cell<Option<usize>>,  // the summed size of the list items, once known
+    attributes: Rc<Table<Self>>,
+    map: Rc<Map<Self>>,
+}
+
+// most values have no string, list, attributes or map entries, so they all share these
+thread_local! {
+    static EMPTY_STRING: Rc<str> = Rc::from("");
+    static EMPTY_LIST: Rc<Vec<Value>> = Rc::new(vec![]);
+    static EMPTY_ATTRIBUTES: Rc<Table<Value>> = Rc::new(Table::new());
+    static EMPTY_MAP: Rc<Map<Value>> = Rc::new(Map::new());
 }
 
 impl Display for Value {
@@ -24,6 +43,18 @@ impl Display for Value {
     }
 }
 
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.value_type == other.value_type
+            && self.function == other.function
+            && self.contents == other.contents
+            && self.string == other.string
+            && self.list == other.list
+            && self.attributes == other.attributes
+            && self.map == other.map
+    }
+}
+
 impl Eq for Value {}
 
 impl PartialOrd for Value {
@@ -39,17 +70,60 @@ impl Ord for Value {
 }
 
 
+// the problem an arithmetic operator results in, if any. a problem
+// operand is passed through unchanged, checking the left one first,
+// so the first problem in a calculation is the one that comes out
+pub(crate) fn operand_problem(lhs: &Value, rhs: &Value, operation: &str, supported: &[Type]) -> Option<Value> {
+    if lhs.is_problem() {
+        return Some(lhs.clone());
+    }
+    if rhs.is_problem() {
+        return Some(rhs.clone());
+    }
+
+    if lhs.value_type != rhs.value_type {
+        return Some(Value::from_problem_with(
+            Problem::IncompatibleTypes,
+            &format!("cannot {} {:?} and {:?} values", operation, lhs.value_type, rhs.value_type),
+            None
+        ));
+    }
+
+    if !supported.contains(&lhs.value_type) {
+        return Some(Value::from_problem_with(
+            Problem::TypeError,
+            &format!("cannot {} {:?} values", operation, lhs.value_type),
+            None
+        ));
+    }
+
+    None
+}
+
+// dividing by zero, or taking a remainder by it, gives a
+// DivisionByZero problem with the dividend as its payload
+pub(crate) fn zero_divisor(dividend: &Value, divisor: &Value) -> Option<Value> {
+    if !divisor.as_number().is_zero() {
+        return None;
+    }
+    Some(Value::from_problem_with(
+        Problem::DivisionByZero,
+        &format!("cannot divide {} by zero", dividend.as_number()),
+        Some(dividend.clone())
+    ))
+}
+
+
 impl Add for Value {
     type Output = Value;
     fn add(self, rhs: Self) -> Self::Output {
-        if self.value_type != rhs.value_type {
-            return Value::from_problem(Problem::IncompatibleTypes);
+        if let Some(problem) = operand_problem(&self, &rhs, "add", &[Type::Num, Type::Str]) {
+            return problem;
         }
 
         match self.value_type {
-            Type::Num => Value::from_number(self.as_number() + rhs.as_number()),
-            Type::Str => Value::from_string(self.as_string() + &rhs.as_string()),
-            _ => Value::from_problem(Problem::ValueError)
+            Type::Str => Value::from_string(self.as_string() + rhs.get_string()),
+            _ => Value::from_number(self.as_number() + rhs.as_number())
         }
     }
 }
@@ -57,63 +131,50 @@ impl Add for Value {
 impl Sub for Value {
     type Output = Value;
     fn sub(self, rhs: Self) -> Self::Output {
-        if self.value_type != rhs.value_type {
-            return Value::from_problem(Problem::IncompatibleTypes);
-        }
-
-        if self.value_type != Type::Num {
-            return Value::from_problem(Problem::ValueError);
+        if let Some(problem) = operand_problem(&self, &rhs, "subtract", &[Type::Num]) {
+            return problem;
         }
 
-        return Value::from_number(self.as_number() - rhs.as_number())
+        Value::from_number(self.as_number() - rhs.as_number())
     }
 }
 
 impl Mul for Value {
     type Output = Value;
     fn mul(self, rhs: Self) -> Self::Output {
-        // self.println();
-        // rhs.println();
-        
-        if self.value_type != rhs.value_type {
-            return Value::from_problem(Problem::IncompatibleTypes);
-        }
-
-        if self.value_type != Type::Num {
-            return Value::from_problem(Problem::ValueError);
+        if let Some(problem) = operand_problem(&self, &rhs, "multiply", &[Type::Num]) {
+            return problem;
         }
 
-        return Value::from_number(self.as_number() * rhs.as_number())
+        Value::from_number(self.as_number() * rhs.as_number())
     }
 }
 
 impl Div for Value {
     type Output = Value;
     fn div(self, rhs: Self) -> Self::Output {
-        if self.value_type != rhs.value_type {
-            return Value::from_problem(Problem::IncompatibleTypes);
+        if let Some(problem) = operand_problem(&self, &rhs, "divide", &[Type::Num]) {
+            return problem;
         }
-
-        if self.value_type != Type::Num {
-            return Value::from_problem(Problem::ValueError);
+        if let Some(problem) = zero_divisor(&self, &rhs) {
+            return problem;
         }
 
-        return Value::from_number(self.as_number() / rhs.as_number())
+        Value::from_number(self.as_number() / rhs.as_number())
     }
 }
 
 impl Rem for Value {
     type Output = Value;
     fn rem(self, rhs: Self) -> Self::Output {
-        if self.value_type != rhs.value_type {
-            return Value::from_problem(Problem::IncompatibleTypes);
+        if let Some(problem) = operand_problem(&self, &rhs, "take the remainder of", &[Type::Num]) {
+            return problem;
         }
-
-        if self.value_type != Type::Num {
-            return Value::from_problem(Problem::ValueError);
+        if let Some(problem) = zero_divisor(&self, &rhs) {
+            return problem;
         }
 
-        return Value::from_number(self.as_number() % rhs.as_number())
+        Value::from_number(self.as_number() % rhs.as_number())
     }
 }
 
@@ -121,14 +182,8 @@ impl Not for Value {
     type Output = Value;
     fn not(self) -> Self::Output {
         match self.value_type {
-            Type::Num => {
-                if self == num("0") {
-                    return num("1");
-                } else {
-                    return num("0");
-                }
-            },
-            _ => num("0")
+            Type::Problem(_) => self,
+            _ => Value::from_bool(!self.is_truthy())
         }
     }
 }
@@ -138,23 +193,158 @@ impl Not for Value {
 impl Object for Value {
     fn new(value_type: Type, contents: Contents) -> Self {
         Self {
-            value_type: value_type,
-            contents: contents,
-            function: |object: Self| object,
-            list: vec![],
-            attributes: Table::new()
+            value_type,
+            contents: Rc::new(contents),
+            string: EMPTY_STRING.with(Rc::clone),
+            function: None,
+            list: EMPTY_LIST.with(Rc::clone),
+            list_size: Cell::new(Some(0)),
+            attributes: EMPTY_ATTRIBUTES.with(Rc::clone),
+            map: EMPTY_MAP.with(Rc::clone)
         }
     }
 
-    fn get_type(&self) -> Type {self.value_type.clone()}
-    fn get_list(&self) -> Vec<Self> {self.list.clone()}
-    fn get_contents(&self) -> Contents {self.contents.clone()}
-    fn get_attributes(&self) -> Table<Self> {self.attributes.clone()}
-    fn get_foreign_function(&self) -> fn(Self) -> Self {self.function.clone()}
+    fn get_type(&self) -> Type {self.value_type}
+    fn get_list(&self) -> &[Self] {&self.list}
+    fn get_contents(&self) -> &[Number] {&self.contents}
+    fn get_string(&self) -> &str {&self.string}
+    fn get_attributes(&self) -> &Table<Self> {&self.attributes}
+    fn get_map(&self) -> &Map<Self> {&self.map}
+    fn get_list_mut(&mut self) -> &mut Vec<Self> {
+        // the items may be changed in any way, so their size is counted again
+        self.list_size.set(None);
+        Rc::make_mut(&mut self.list)
+    }
+    fn get_contents_mut(&mut self) -> &mut Contents {Rc::make_mut(&mut self.contents)}
+    fn get_attributes_mut(&mut self) -> &mut Table<Self> {Rc::make_mut(&mut self.attributes)}
+    fn get_map_mut(&mut self) -> &mut Map<Self> {Rc::make_mut(&mut self.map)}
+    fn get_foreign_function(&self) -> ForeignFunction<Self> {
+        match &self.function {
+            Some(f) => f.clone(),
+            None => ForeignFunction::new(Ok)
+        }
+    }
+    fn is_foreign_function(&self) -> bool {self.function.is_some()}
+
+    fn size(&self) -> usize {
+        1 + self.contents.len()
+            + self.string.chars().count()
+            + self.list_size()
+            + self.attributes.iter().map(|(_, v)| v.size()).sum::<usize>()
+            + self.map.size()
+    }
 
     fn set_type(&mut self, value_type: Type) {self.value_type = value_type}
-    fn set_list(&mut self, list: Vec<Self>) {self.list = list}
-    fn set_contents(&mut self, contents: Contents) {self.contents = contents}
-    fn set_attributes(&mut self, attributes: Table<Self>) {self.attributes = attributes}
-    fn set_foreign_function(&mut self, function: fn(Self) -> Self) {self.function = function}
-}
\ No newline at end of file
+    fn set_list(&mut self, list: Vec<Self>) {
+        self.list = Rc::new(list);
+        self.list_size.set(None);
+    }
+    fn set_contents(&mut self, contents: Contents) {self.contents = Rc::new(contents)}
+    fn set_string(&mut self, string: &str) {self.string = Rc::from(string)}
+    fn set_attributes(&mut self, attributes: Table<Self>) {self.attributes = Rc::new(attributes)}
+    fn set_map(&mut self, map: Map<Self>) {self.map = Rc::new(map)}
+    fn set_foreign_function(&mut self, function: ForeignFunction<Self>) {self.function = Some(function)}
+
+    // pushing keeps the size of the list up to date, so that a list
+    // built one item at a time doesn't count its items again each time
+    fn list_push(&mut self, object: Self) {
+        let size = self.list_size() + object.size();
+        self.set_type(Type::List);
+        Rc::make_mut(&mut self.list).push(object);
+        self.list_size.set(Some(size));
+    }
+}
+
+
+impl Value {
+    // the summed size of the list items, counting them if it isn't known yet
+    fn list_size(&self) -> usize {
+        match self.list_size.get() {
+            Some(size) => size,
+            None => {
+                let size = self.list.iter().map(|v| v.size()).sum();
+                self.list_size.set(Some(size));
+                size
+            }
+        }
+    }
+
+    // encode this value in the binary bytecode format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        bytecode::encode(self)
+    }
+
+    // decode a value from the binary bytecode format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        bytecode::decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::literals::*;
+
+    #[test]
+    fn clones_share_their_storage() {
+        let a = list(&[num("1"), string("two")]);
+        let b = a.clone();
+        assert!(Rc::ptr_eq(&a.list, &b.list));
+        assert!(Rc::ptr_eq(&num("1").list, &num("2").list));
+    }
+
+    #[test]
+    fn changing_a_clone_copies_it() {
+        let a = list(&[num("1")]);
+        let mut b = a.clone();
+        b.list_push(num("2"));
+        assert!(!Rc::ptr_eq(&a.list, &b.list));
+        assert_eq!(a.get_list().len(), 1);
+        assert_eq!(b.get_list().len(), 2);
+
+        let mut instance = empty_obj();
+        instance.set_attr("x".to_string(), num("1"));
+        let mut copy = instance.clone();
+        copy.set_attr("x".to_string(), num("2"));
+        assert_eq!(instance.get_attr("x".to_string()), num("1"));
+        assert_eq!(copy.get_attr("x".to_string()), num("2"));
+    }
+
+    #[test]
+    fn values_that_are_not_shared_are_changed_in_place() {
+        let mut a = list(&[num("1")]);
+        let before = Rc::as_ptr(&a.list);
+        a.get_list_mut().push(num("2"));
+        assert_eq!(Rc::as_ptr(&a.list), before);
+    }
+
+    #[test]
+    fn sizes_follow_changes_to_the_list() {
+        let mut a = list(&[num("1"), string("two")]);
+        assert_eq!(a.size(), 1 + num("1").size() + string("two").size());
+
+        a.list_push(list(&[num("3")]));
+        assert_eq!(a.size(), 1 + num("1").size() + string("two").size() + list(&[num("3")]).size());
+
+        a.get_list_mut().clear();
+        assert_eq!(a.size(), 1);
+    }
+
+    #[test]
+    fn dividing_by_zero_is_a_problem() {
+        for result in [num("1") / num("0"), num("1.5") % num("0.0")].iter() {
+            assert_eq!(result.get_type(), Type::Problem(Problem::DivisionByZero));
+        }
+        let result = num("7") / num("0");
+        assert_eq!(result.problem_payload(), Some(num("7")));
+        assert_eq!(result.problem_message(), Some("cannot divide 7 by zero"));
+    }
+
+    #[test]
+    fn values_sort_nan_after_every_number() {
+        let mut values = vec![num("NaN"), num("Infinity"), num("-Infinity"), num("1")];
+        values.sort();
+        assert_eq!(values, [num("-Infinity"), num("1"), num("Infinity"), num("NaN")]);
+        assert_eq!(num("NaN"), num("NaN"));
+    }
+}